@@ -0,0 +1,95 @@
+// Paints a batch of brush dabs into a state texture, copying the cells they
+// miss and the walls through from `input`. The storage format of `output` is
+// picked with a shader def, as in `pattern.compute.wgsl`; with `CLAMP_STATE`
+// the painted cells are clamped into [0, 1].
+#import simulation::grid::is_solid
+
+#ifdef RGBA8UNORM
+@group(0) @binding(0) var output: texture_storage_2d<rgba8unorm, write>;
+#else ifdef RGBA16FLOAT
+@group(0) @binding(0) var output: texture_storage_2d<rgba16float, write>;
+#else ifdef RGBA32FLOAT
+@group(0) @binding(0) var output: texture_storage_2d<rgba32float, write>;
+#else ifdef R16FLOAT
+@group(0) @binding(0) var output: texture_storage_2d<r16float, write>;
+#else ifdef RG32FLOAT
+@group(0) @binding(0) var output: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(0) var output: texture_storage_2d<r32float, write>;
+#endif
+
+const MODE_ADD: u32 = 0u;
+const MODE_ERASE: u32 = 1u;
+const MODE_SET: u32 = 2u;
+const MODE_NOISE: u32 = 3u;
+
+struct Dab {
+    center: vec2<f32>,
+    radius: f32,
+    strength: f32,
+    // fraction of the radius over which the brush fades out
+    falloff: f32,
+    mode: u32,
+    seed: u32,
+}
+@group(0) @binding(1) var<storage, read> dabs: array<Dab>;
+
+@group(0) @binding(2) var input: texture_2d<f32>;
+
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random_float(value: u32) -> f32 {
+    return f32(hash(value)) / 4294967295.0;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn paint(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    let size = vec2<i32>(textureDimensions(output));
+    if any(location >= size) {
+        return;
+    }
+    var value = textureLoad(input, location, 0);
+    if is_solid(location) {
+        textureStore(output, location, value);
+        return;
+    }
+    // in order, so that overlapping dabs stack
+    for (var i = 0u; i < arrayLength(&dabs); i++) {
+        let dab = dabs[i];
+        let distance = length(vec2<f32>(location) + 0.5 - dab.center) / dab.radius;
+        if distance > 1.0 {
+            continue;
+        }
+        let weight = clamp((1.0 - distance) / max(dab.falloff, 1e-4), 0.0, 1.0);
+        switch dab.mode {
+            case MODE_ADD: {
+                value += dab.strength * weight;
+            }
+            case MODE_ERASE: {
+                value = mix(value, vec4<f32>(0.0), clamp(dab.strength * weight, 0.0, 1.0));
+            }
+            case MODE_SET: {
+                value = mix(value, vec4<f32>(dab.strength), weight);
+            }
+            case MODE_NOISE, default: {
+                let cell = u32(location.y * size.x + location.x) * 4u;
+                let noise = vec4<f32>(
+                    random_float(hash(dab.seed) ^ cell),
+                    random_float(hash(dab.seed) ^ (cell + 1u)),
+                    random_float(hash(dab.seed) ^ (cell + 2u)),
+                    random_float(hash(dab.seed) ^ (cell + 3u)),
+                );
+                value = mix(value, noise * dab.strength, weight);
+            }
+        }
+    }
+#ifdef CLAMP_STATE
+    value = clamp(value, vec4<f32>(0.0), vec4<f32>(1.0));
+#endif
+    textureStore(output, location, value);
+}
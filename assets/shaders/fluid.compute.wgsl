@@ -1,14 +1,32 @@
-@group(0) @binding(0) var colorMap: texture_storage_2d<rgba8unorm, read_write>;
-@group(0) @binding(1) var velocityXMap: texture_storage_2d<r32float, read_write>;
-@group(0) @binding(2) var velocityYMap: texture_storage_2d<r32float, read_write>;
-@group(0) @binding(3) var pressureMap: texture_storage_2d<r32float, read_write>;
+#import simulation::grid::{grid, load, in_grid, is_solid, store_display, BOUNDARY_PERIODIC, BOUNDARY_REFLECTING}
+
+// double buffered fields: read the previous pass from `*In`, write to `*Out`
+@group(0) @binding(0) var colorIn: texture_2d<f32>;
+@group(0) @binding(1) var colorOut: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(2) var velocityXIn: texture_2d<f32>;
+@group(0) @binding(3) var velocityXOut: texture_storage_2d<r32float, write>;
+@group(0) @binding(4) var velocityYIn: texture_2d<f32>;
+@group(0) @binding(5) var velocityYOut: texture_storage_2d<r32float, write>;
+@group(0) @binding(6) var pressureIn: texture_2d<f32>;
+@group(0) @binding(7) var pressureOut: texture_storage_2d<r32float, write>;
+@group(1) @binding(0) var<uniform> rho: f32; // density
+
+// velocity and dye injected by the mouse drag, see `FluidSplat`
+struct Splat {
+    position: vec2<f32>,
+    force: vec2<f32>,
+    color: vec4<f32>,
+    radius: f32,
+}
+struct Splats {
+    count: u32,
+    splats: array<Splat, 16>,
+}
+@group(1) @binding(1) var<storage, read_write> splats: Splats;
 
 const RED = vec4<f32>(1.0, 0.0, 0.0, 1.0);
 const GREEN = vec4<f32>(0.0, 1.0, 0.0, 1.0);
 const BLUE = vec4<f32>(0.0, 0.0, 1.0, 1.0);
-const rho = 1.00;
-// const rho = 0.99;
-const RESOLUTION = vec2<i32>(600, 400);
 
 const ring_radius = 25;
 const mu = 0.14;     // growth center
@@ -29,6 +47,9 @@ fn growth(U: f32, m: f32, s: f32) -> f32 {
 @compute @workgroup_size(8, 8, 1)
 fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
     let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
     var color = vec4<f32>(0.0);
     var velocity_x = vec4<f32>(0.0);
     var velocity_y = vec4<f32>(0.0);
@@ -43,13 +64,13 @@ fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_wo
         // color = BLUE;
     }
 
-    if i32(location.x) < 300 {
+    if location.x < grid.size.x / 2 {
         velocity_y = vec4<f32>(-0.2);
     } else {
         velocity_y = vec4<f32>(0.2);
     }
 
-    if i32(location.y) < 200 {
+    if location.y < grid.size.y / 2 {
         velocity_x = vec4<f32>(0.3);
     } else {
         velocity_x = vec4<f32>(-0.3);
@@ -60,34 +81,58 @@ fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_wo
     // if 250 < location.x && location.x < 350 && 150 < location.y && location.y < 250 {
     //     pressure = vec4<f32>(-50.0);
     // }
-    textureStore(colorMap, location, color);
-    textureStore(velocityXMap, location, velocity_x);
-    textureStore(velocityYMap, location, velocity_y);
-    textureStore(pressureMap, location, pressure);
-}
-
-fn wrap_coord(coord: vec2<i32>) -> vec2<i32> {
-    let wrapped_x = (coord.x % RESOLUTION.x + RESOLUTION.x) % RESOLUTION.x;
-    let wrapped_y = (coord.y % RESOLUTION.y + RESOLUTION.y) % RESOLUTION.y;
-    return vec2<i32>(wrapped_x, wrapped_y);
+    if is_solid(location) {
+        color = vec4<f32>(0.0);
+        velocity_x = vec4<f32>(0.0);
+        velocity_y = vec4<f32>(0.0);
+    }
+    textureStore(colorOut, location, color);
+    textureStore(velocityXOut, location, velocity_x);
+    textureStore(velocityYOut, location, velocity_y);
+    textureStore(pressureOut, location, pressure);
 }
 
 fn get_color(location: vec2<i32>) -> vec4<f32> {
-    let value: vec4<f32> = textureLoad(colorMap, wrap_coord(location));
+    let value: vec4<f32> = load(colorIn, location);
     return value;
 }
 
 fn get_velocity(location: vec2<i32>) -> vec2<f32> {
-    let valueX = textureLoad(velocityXMap, wrap_coord(location)).x;
-    let valueY = textureLoad(velocityYMap, wrap_coord(location)).x;
-    return vec2<f32>(valueX, valueY);
+    // obstacles are no-slip walls
+    if is_solid(location) {
+        return vec2<f32>(0.0);
+    }
+    let valueX = load(velocityXIn, location).x;
+    let valueY = load(velocityYIn, location).x;
+    var velocity = vec2<f32>(valueX, valueY);
+    // behind a reflecting wall the mirrored flow moves the other way, so the
+    // velocity through the wall averages out to zero
+    if grid.boundary == BOUNDARY_REFLECTING {
+        let outside = location < vec2<i32>(0) | location >= grid.size;
+        velocity = select(velocity, -velocity, outside);
+    }
+    return velocity;
 }
 
 fn get_pressure(location: vec2<i32>) -> f32 {
-    let value: vec4<f32> = textureLoad(pressureMap, wrap_coord(location));
+    // solid walls have no pressure gradient across them
+    if grid.boundary != BOUNDARY_PERIODIC && !in_grid(location) {
+        let wall = clamp(location, vec2<i32>(0), grid.size - 1);
+        return textureLoad(pressureIn, wall, 0).x;
+    }
+    let value: vec4<f32> = load(pressureIn, location);
     return value.x;
 }
 
+// pressure of the neighbour at `offset`, or of the cell itself when the
+// neighbour is an obstacle, so there is no pressure gradient into walls
+fn get_neighbour_pressure(location: vec2<i32>, offset: vec2<i32>) -> f32 {
+    if is_solid(location + offset) {
+        return get_pressure(location);
+    }
+    return get_pressure(location + offset);
+}
+
 fn sample_velocity(pos: vec2<f32>) -> vec2<f32> {
     // オフセットの整数部分と小数部分を取得
     let pos00 = vec2<i32>(floor(pos));
@@ -110,7 +155,7 @@ fn sample_velocity(pos: vec2<f32>) -> vec2<f32> {
     return result;
 }
 
-fn update_color(location: vec2<i32>) {
+fn advect_color(location: vec2<i32>) -> vec4<f32> {
     let velocity = get_velocity(location + vec2(0));
 
     let pos = vec2<f32>(location) - velocity;
@@ -126,38 +171,25 @@ fn update_color(location: vec2<i32>) {
     let value01 = get_color(pos00 + vec2<i32>(0, 1));
     let value11 = get_color(pos00 + vec2<i32>(1, 1));
 
-    // 各セルに分配する比率を計算
-    let weight00 = (1.0 - pos_fract.x) * (1.0 - pos_fract.y);
-    let weight10 = pos_fract.x * (1.0 - pos_fract.y);
-    let weight01 = (1.0 - pos_fract.x) * pos_fract.y;
-    let weight11 = pos_fract.x * pos_fract.y;
-
     // 水平方向の補間
     let value0 = mix(value00, value10, pos_fract.x);
     let value1 = mix(value01, value11, pos_fract.x);
 
     // 垂直方向の補間
-    let newColor = mix(value0, value1, pos_fract.y);
-
-
-    set_color(pos00, vec2<i32>(0, 0), flow00);
-    set_color(pos00, vec2<i32>(1, 0), flow10);
-    set_color(pos00, vec2<i32>(0, 1), flow01);
-    set_color(pos00, vec2<i32>(1, 1), flow11);
-    set_color(location, vec2<i32>(0, 0), value * alpha);
-
+    return mix(value0, value1, pos_fract.y);
+}
 
-    // textureStore(colorMap, location, newColor);
-    textureStore(colorMap, wrap_coord(location), newColor);
+// weight of splat `i` at `location`, a Gaussian of its radius
+fn splat_weight(i: u32, location: vec2<i32>) -> f32 {
+    let offset = vec2<f32>(location) + 0.5 - splats.splats[i].position;
+    let radius = splats.splats[i].radius;
+    return exp(-dot(offset, offset) / (radius * radius));
 }
 
-fn update_velocity(location: vec2<i32>) {
+fn advect_velocity(location: vec2<i32>) -> vec2<f32> {
     let velocity = -get_velocity(location + vec2(0));
     let newVelocity: vec2<f32> = sample_velocity(vec2<f32>(location) + velocity);
-    // textureStore(velocityXMap, location, vec4(newVelocity.x));
-    // textureStore(velocityYMap, location, vec4(newVelocity.y));
-    textureStore(velocityXMap, wrap_coord(location), vec4(newVelocity.x));
-    textureStore(velocityYMap, wrap_coord(location), vec4(newVelocity.y));
+    return newVelocity;
 }
 
 fn calc_divergence(location: vec2<i32>) -> f32 {
@@ -172,44 +204,46 @@ fn calc_divergence(location: vec2<i32>) -> f32 {
 @compute @workgroup_size(8, 8, 1)
 fn update_pressure(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
     let divergence = calc_divergence(location);
 
-    let left_in = get_pressure(location + vec2(-1,0));
-    let right_in = get_pressure(location + vec2(1,0));
-    let top_in = get_pressure(location + vec2(0,-1));
-    let bottom_in = get_pressure(location + vec2(0,1));
+    let left_in = get_neighbour_pressure(location, vec2(-1,0));
+    let right_in = get_neighbour_pressure(location, vec2(1,0));
+    let top_in = get_neighbour_pressure(location, vec2(0,-1));
+    let bottom_in = get_neighbour_pressure(location, vec2(0,1));
 
     let result = 0.25 * (divergence + left_in + right_in + top_in + bottom_in);
-    // for (var i = 0; i < 1000; i++) {
-    //     textureStore(pressureMap, wrap_coord(location), vec4(result));
-    // }
-    textureStore(pressureMap, wrap_coord(location), vec4(result));
+    textureStore(pressureOut, location, vec4(result));
 }
 
-fn gradient_subtract(location: vec2<i32>) {
-    let left_pressure = get_pressure(location + vec2(-1,0));
-    let right_pressure = get_pressure(location + vec2(1,0));
-    let top_pressure = get_pressure(location + vec2(0,-1));
-    let bottom_pressure = get_pressure(location + vec2(0,1));
+// subtract the pressure gradient from the advected velocity
+fn gradient_subtract(location: vec2<i32>, velocity: vec2<f32>) {
+    let left_pressure = get_neighbour_pressure(location, vec2(-1,0));
+    let right_pressure = get_neighbour_pressure(location, vec2(1,0));
+    let top_pressure = get_neighbour_pressure(location, vec2(0,-1));
+    let bottom_pressure = get_neighbour_pressure(location, vec2(0,1));
 
     let pressure_diff_x = (right_pressure - left_pressure) * 0.5;
     let pressure_diff_y = (bottom_pressure - top_pressure) * 0.5;
 
-    let velocity = get_velocity(location + vec2(0));
-
     let final_velocity_x = velocity.x - pressure_diff_x / rho;
     let final_velocity_y = velocity.y - pressure_diff_y / rho;
 
     // let final_velocity = normalize(vec2<f32>(final_velocity_x, final_velocity_y));
-    let final_velocity = vec2<f32>(final_velocity_x, final_velocity_y);
+    let final_velocity = select(vec2<f32>(final_velocity_x, final_velocity_y), vec2<f32>(0.0), is_solid(location));
 
-    textureStore(velocityXMap, wrap_coord(location), vec4(final_velocity.x));
-    textureStore(velocityYMap, wrap_coord(location), vec4(final_velocity.y));
+    textureStore(velocityXOut, location, vec4(final_velocity.x));
+    textureStore(velocityYOut, location, vec4(final_velocity.y));
 }
 
 @compute @workgroup_size(8, 8, 1)
 fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
     // let velocity = get_velocity(location, 0, 0);
     // let color = get_color(location, 0, 0);
 
@@ -217,12 +251,33 @@ fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     // if velocity > 0.0 {
     //     textureStore(colorMap, location, vec4<f32>(color - 0.01));
     // }
-    update_color(location);
-    update_velocity(location);
-    
+    var color = advect_color(location);
+    var velocity = advect_velocity(location);
+    for (var i = 0u; i < splats.count; i++) {
+        let weight = splat_weight(i, location);
+        color = mix(color, splats.splats[i].color, weight);
+        velocity += splats.splats[i].force * weight;
+    }
+    textureStore(colorOut, location, select(color, vec4<f32>(0.0), is_solid(location)));
+
     // update_pressure(location, divergence);
-    gradient_subtract(location);
+    gradient_subtract(location, velocity);
     // if 250 < location.x && location.x < 350 && 150 < location.y && location.y < 250 {
     //     textureStore(pressureMap, location, vec4(1.0));
     // }
 }
+
+// empties the splats once the first step of a frame injected them
+@compute @workgroup_size(1, 1, 1)
+fn consume_splats() {
+    splats.count = 0u;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn display(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
+    store_display(location, get_color(location));
+}
@@ -1,4 +1,16 @@
-@group(0) @binding(0) var texture: texture_storage_2d<rgba8unorm, read_write>;
+#import simulation::grid::{grid, load, is_solid, store_display}
+
+// double buffered state: read the previous step from `input`, write to `output`
+@group(0) @binding(0) var input: texture_2d<f32>;
+@group(0) @binding(1) var output: texture_storage_2d<rgba8unorm, write>;
+
+// outer totalistic rule: bit n is set when n live neighbours give birth to a
+// dead cell, or keep a live one alive
+struct Rule {
+    birth: u32,
+    survival: u32,
+}
+@group(1) @binding(0) var<uniform> rule: Rule;
 
 fn hash(value: u32) -> u32 {
     var state = value;
@@ -18,17 +30,20 @@ fn randomFloat(value: u32) -> f32 {
 @compute @workgroup_size(8, 8, 1)
 fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
     let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
 
     let randomNumber = randomFloat(invocation_id.y * num_workgroups.x + invocation_id.x);
-    let alive = randomNumber > 0.9;
+    let alive = randomNumber > 0.9 && !is_solid(location);
     let color = vec4<f32>(f32(alive));
 
-    textureStore(texture, location, color);
+    textureStore(output, location, color);
 }
 
 fn is_alive(location: vec2<i32>, offset_x: i32, offset_y: i32) -> i32 {
-    let value: vec4<f32> = textureLoad(texture, location + vec2<i32>(offset_x, offset_y));
-    return i32(value.x);
+    let value: vec4<f32> = load(input, location + vec2<i32>(offset_x, offset_y));
+    return i32(value.x > 0.5);
 }
 
 fn count_alive(location: vec2<i32>) -> i32 {
@@ -45,21 +60,28 @@ fn count_alive(location: vec2<i32>) -> i32 {
 @compute @workgroup_size(8, 8, 1)
 fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
 
-    let n_alive = count_alive(location);
+    let n_alive = u32(count_alive(location));
 
-    var alive: bool;
-    if (n_alive == 3) {
-        alive = true;
-    } else if (n_alive == 2) {
-        let currently_alive = is_alive(location, 0, 0);
-        alive = bool(currently_alive);
-    } else {
-        alive = false;
+    var mask = rule.birth;
+    if is_alive(location, 0, 0) == 1 {
+        mask = rule.survival;
     }
+    let alive = ((mask >> n_alive) & 1u) == 1u && !is_solid(location);
     let color = vec4<f32>(f32(alive));
 
-    storageBarrier();
+    textureStore(output, location, color);
+}
 
-    textureStore(texture, location, color);
+@compute @workgroup_size(8, 8, 1)
+fn display(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
+    let alive = f32(is_alive(location, 0, 0));
+    store_display(location, vec4<f32>(vec3<f32>(alive), 1.0));
 }
@@ -0,0 +1,71 @@
+#define_import_path simulation::grid
+
+// bindings every simulation gets from the framework, see `GridUniform` in simulation.rs
+struct Grid {
+    size: vec2<i32>,
+    boundary: u32, // one of the BOUNDARY_* constants
+}
+
+@group(2) @binding(0) var<uniform> grid: Grid;
+// colour image shown on the sprite, written by the display entry point
+@group(2) @binding(1) var display_output: texture_storage_2d<rgba8unorm, write>;
+// nonzero where a cell is solid; stretched over the grid whatever its size
+@group(2) @binding(2) var obstacle_mask: texture_2d<f32>;
+
+const OBSTACLE_COLOR = vec4<f32>(0.35, 0.35, 0.4, 1.0);
+
+// must match `Boundary` in simulation.rs
+const BOUNDARY_PERIODIC = 0u;
+const BOUNDARY_CLAMPED = 1u;
+const BOUNDARY_ZERO = 2u;
+const BOUNDARY_REFLECTING = 3u;
+
+fn in_grid(coord: vec2<i32>) -> bool {
+    return all(coord >= vec2<i32>(0)) && all(coord < grid.size);
+}
+
+// mirrors `x` back into [0, n), repeating the edge cell
+fn mirror(x: i32, n: i32) -> i32 {
+    let period = 2 * n;
+    let m = (x % period + period) % period;
+    return select(m, period - 1 - m, m >= n);
+}
+
+// the cell of the grid that `coord` stands for under the boundary condition;
+// with BOUNDARY_ZERO cells outside of the grid have no counterpart, see `load`
+fn boundary_coord(coord: vec2<i32>) -> vec2<i32> {
+    switch grid.boundary {
+        case BOUNDARY_CLAMPED, BOUNDARY_ZERO: {
+            return clamp(coord, vec2<i32>(0), grid.size - 1);
+        }
+        case BOUNDARY_REFLECTING: {
+            return vec2<i32>(mirror(coord.x, grid.size.x), mirror(coord.y, grid.size.y));
+        }
+        default: {
+            return (coord % grid.size + grid.size) % grid.size;
+        }
+    }
+}
+
+// reads `texture` at `coord`, which may lie outside of the grid
+fn load(texture: texture_2d<f32>, coord: vec2<i32>) -> vec4<f32> {
+    if grid.boundary == BOUNDARY_ZERO && !in_grid(coord) {
+        return vec4<f32>(0.0);
+    }
+    return textureLoad(texture, boundary_coord(coord), 0);
+}
+
+// whether the cell at `coord`, which may lie outside of the grid, is a wall
+fn is_solid(coord: vec2<i32>) -> bool {
+    if grid.boundary == BOUNDARY_ZERO && !in_grid(coord) {
+        return false;
+    }
+    let mask_size = vec2<i32>(textureDimensions(obstacle_mask));
+    let texel = boundary_coord(coord) * mask_size / grid.size;
+    return textureLoad(obstacle_mask, texel, 0).x > 0.5;
+}
+
+// writes the displayed colour of the cell at `location`, drawing walls on top
+fn store_display(location: vec2<i32>, color: vec4<f32>) {
+    textureStore(display_output, location, select(color, OBSTACLE_COLOR, is_solid(location)));
+}
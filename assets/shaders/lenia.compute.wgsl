@@ -1,18 +1,65 @@
-@group(0) @binding(0) var texture: texture_storage_2d<rgba8unorm, read_write>;
+#import simulation::grid::{grid, load, is_solid, store_display}
 
-const RESOLUTION = vec2<i32>(600, 400);
-const ring_radius = 15;
-const mu = 0.14;     // growth center
-const sigma = 0.014; // growth width
-const rho = 0.5;     // kernel center
-const omega = 0.15;  // kernel width
+struct LeniaParams {
+    dt: f32,           // time step
+    integrator: u32,   // one of the INTEGRATOR_* constants
+    channels: u32,     // channels in use, up to 3
+    kernel_count: u32, // number of entries in `kernels`
+}
 
-fn wrap_coord(coord: vec2<i32>) -> vec2<i32> {
-    let wrapped_x = (coord.x % RESOLUTION.x + RESOLUTION.x) % RESOLUTION.x;
-    let wrapped_y = (coord.y % RESOLUTION.y + RESOLUTION.y) % RESOLUTION.y;
-    return vec2<i32>(wrapped_x, wrapped_y);
+// one kernel of the channel wiring, see `ChannelKernel` in lenia.rs
+struct Kernel {
+    source: u32,         // channel the kernel reads
+    destination: u32,    // channel the growth is added to
+    weight: f32,         // scale of the growth in the destination channel
+    mu: f32,             // growth center, or alpha_n of GROWTH_SMOOTH_LIFE
+    sigma: f32,          // growth width, or alpha_m of GROWTH_SMOOTH_LIFE
+    growth: u32,         // one of the GROWTH_* constants
+    radius: i32,         // kernel radius in cells
+    weights_offset: u32, // index of the first weight in `kernel_weights`
+    // GROWTH_INTERVALS: birth and survival intervals of the potential,
+    // GROWTH_SMOOTH_LIFE: birth and death intervals of the outer filling
+    intervals: vec4<f32>,
 }
 
+// must match `GrowthFunction` in lenia.rs
+const GROWTH_GAUSSIAN = 0u;
+const GROWTH_POLYNOMIAL = 1u;
+const GROWTH_STEP = 2u;
+const GROWTH_STATE_MODULATED = 3u;
+const GROWTH_INTERVALS = 4u;
+const GROWTH_FILLING = 5u;
+const GROWTH_SMOOTH_LIFE = 6u;
+
+// must match `Integrator` in lenia.rs
+const INTEGRATOR_EULER = 0u;
+const INTEGRATOR_SOFT_CLIP = 1u;
+const INTEGRATOR_ASYMPTOTIC = 2u;
+// width of the rounded corners of INTEGRATOR_SOFT_CLIP, must match `SOFT_CLIP_WIDTH` in lenia.rs
+const SOFT_CLIP_WIDTH = 0.05;
+
+// double buffered state: read the previous step from `input`, write to `output`;
+// channel `c` of the rule is component `c` of the texture
+@group(0) @binding(0) var input: texture_2d<f32>;
+#ifdef R16FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<r16float, write>;
+#else ifdef RGBA32FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rgba32float, write>;
+#else ifdef RGBA16FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rgba16float, write>;
+#else
+@group(0) @binding(1) var output: texture_storage_2d<r32float, write>;
+#endif
+@group(1) @binding(0) var<uniform> params: LeniaParams;
+// row major spectra of all kernels back to back, already scaled for the inverse FFT
+@group(1) @binding(1) var<storage, read> kernel_spectra: array<vec2<f32>>;
+// row major (2R+1)^2 weights of all kernels back to back, each normalised to sum to one
+@group(1) @binding(2) var<storage, read> kernel_weights: array<f32>;
+@group(1) @binding(3) var<storage, read> kernels: array<Kernel>;
+// FFT convolution only: the spectrum of every channel followed by the
+// convolved spectrum of every kernel, each one grid in size
+@group(1) @binding(4) var<storage, read_write> fft_scratch: array<vec2<f32>>;
+
 fn hash(p: vec2<f32>) -> f32 {
     let p2 = dot(p, vec2<f32>(127.1, 311.7));
     return -1.0 + 2.0 * fract(sin(p2) * 43758.5453123);
@@ -22,62 +69,298 @@ fn bell(x: f32, mu: f32, sigma: f32) -> f32 {
     // bell curve
     return exp(-((x - mu) * (x - mu)) / (2.0 * sigma * sigma));
 }
-fn growth(U: f32, m: f32, s: f32) -> f32 {
-    let g = bell(U, m, s) * 2.0 - 1.0;
-    return g;
+// smooth step from 0 to 1 around `a` with width `alpha`, as in SmoothLife
+fn sigmoid(x: f32, a: f32, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + exp(-(x - a) * 4.0 / alpha));
+}
+
+// maps the potential U of `kernel` to a growth rate in [-1, 1]; `filling` is
+// the potential of the last GROWTH_FILLING kernel
+fn growth(kernel: Kernel, U: f32, current_status: f32, filling: f32) -> f32 {
+    let m = kernel.mu;
+    let s = kernel.sigma;
+    switch kernel.growth {
+        case GROWTH_INTERVALS: {
+            // live cells survive within the second interval, dead ones are born within the first
+            let interval = select(kernel.intervals.xy, kernel.intervals.zw, current_status > 0.5);
+            return select(-1.0, 1.0, U >= interval.x && U <= interval.y);
+        }
+        case GROWTH_SMOOTH_LIFE: {
+            // Rafler's transition: the inner filling blends between the birth
+            // and the death interval of the outer filling U
+            let alive = sigmoid(filling, 0.5, s);
+            let low = mix(kernel.intervals.x, kernel.intervals.z, alive);
+            let high = mix(kernel.intervals.y, kernel.intervals.w, alive);
+            return 2.0 * sigmoid(U, low, m) * (1.0 - sigmoid(U, high, m)) - 1.0;
+        }
+        case GROWTH_POLYNOMIAL: {
+            return pow(max(0.0, 1.0 - (U - m) * (U - m) / (9.0 * s * s)), 4.0) * 2.0 - 1.0;
+        }
+        case GROWTH_STEP: {
+            return select(-1.0, 1.0, abs(U - m) <= s);
+        }
+        case GROWTH_STATE_MODULATED: {
+            // the potential is scaled by up to 10% depending on the current state
+            return bell(U * (1.0 + (current_status - 0.5) * 0.2), m, s) * 2.0 - 1.0;
+        }
+        default: {
+            return bell(U, m, s) * 2.0 - 1.0;
+        }
+    }
 }
 
 @compute @workgroup_size(8, 8, 1)
 fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
     let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
-    // let randomNumber = randomFloat(invocation_id.y * num_workgroups.x + invocation_id.x);
-    let randomNumber = hash(vec2<f32>(invocation_id.xy));
+    if any(location >= grid.size) {
+        return;
+    }
     var color = vec4<f32>(0.0);
     if i32(invocation_id.x) < 100 && i32(invocation_id.y) < 300 {
-        color = vec4<f32>(randomNumber);
+        for (var c = 0u; c < params.channels; c++) {
+            // let randomNumber = randomFloat(invocation_id.y * num_workgroups.x + invocation_id.x);
+            let randomNumber = hash(vec2<f32>(invocation_id.xy) + f32(c) * vec2<f32>(17.0, 31.0));
+            color[c] = saturate(randomNumber);
+        }
     }
-    textureStore(texture, location, color);
+    store_state(location, color);
 }
 
-fn get_value(location: vec2<i32>, offset: vec2<i32>) -> f32 {
-    let value: vec4<f32> = textureLoad(texture, wrap_coord(location + offset));
-    return value.x;
+// walls hold no state
+fn store_state(location: vec2<i32>, state: vec4<f32>) {
+    textureStore(output, location, select(state, vec4<f32>(0.0), is_solid(location)));
 }
 
-fn compute_new_state(location: vec2<i32>) -> f32 {
-    var current_status = get_value(location, vec2<i32>(0, 0));
-    var sum: f32 = 0.0;
-    var total: f32 = 0.0;
+fn get_value(location: vec2<i32>, offset: vec2<i32>, channel: u32) -> f32 {
+    let value: vec4<f32> = load(input, location + offset);
+    return value[channel];
+}
 
-    for (var i = -ring_radius; i <= ring_radius; i++) {
-        for (var j = -ring_radius; j <= ring_radius; j++) {
-            let cell_val = get_value(location, vec2<i32>(i, j));
-            let i_f = f32(i);
-            let j_f = f32(j);
-            let r = sqrt((i_f * i_f) + (j_f * j_f)) / f32(ring_radius);
-            let weight = bell(r, rho, omega);
-            sum += cell_val * weight;
-            total += weight;
+// kernel weighted average of the source channel of `kernel` around `location`
+fn potential(location: vec2<i32>, kernel: Kernel) -> f32 {
+    var avg: f32 = 0.0;
+    var tap = kernel.weights_offset;
+    for (var j = -kernel.radius; j <= kernel.radius; j++) {
+        for (var i = -kernel.radius; i <= kernel.radius; i++) {
+            avg += get_value(location, vec2<i32>(i, j), kernel.source) * kernel_weights[tap];
+            tap++;
         }
     }
+    return avg;
+}
 
-    let avg = sum / total;
-    // let g = bell(avg, mu, sigma) * 2.0 - 1.0;
-    // change kernel depending on current_status
-    let g = growth(avg * (1.0 + (current_status - 0.5)*0.2), mu, sigma);
-    let result = saturate(current_status + 0.1 * g);
-    return result;
+// clamps `x` into [0, 1] with the corners rounded off: the identity between
+// SOFT_CLIP_WIDTH and 1 - SOFT_CLIP_WIDTH, a cubic easing into 0 and 1 with
+// matching slopes at both ends of each corner
+fn soft_clip(x: f32) -> f32 {
+    let w = SOFT_CLIP_WIDTH;
+    let low = saturate(x);
+    let high = saturate(1.0 - x);
+    if low < w {
+        return low * low * (2.0 * w - low) / (w * w);
+    }
+    if high < w {
+        return 1.0 - high * high * (2.0 * w - high) / (w * w);
+    }
+    return x;
+}
+
+// advances one channel by the summed growth `g` of the kernels writing into it
+fn integrate(current_status: f32, g: f32) -> f32 {
+    switch params.integrator {
+        case INTEGRATOR_SOFT_CLIP: {
+            return soft_clip(current_status + params.dt * g);
+        }
+        case INTEGRATOR_ASYMPTOTIC: {
+            // relax towards the target state the growth maps to
+            let target_state = g * 0.5 + 0.5;
+            return current_status + params.dt * (target_state - current_status);
+        }
+        default: {
+            return saturate(current_status + params.dt * g);
+        }
+    }
+}
+
+// growth of the kernels summed per destination channel, which channels any
+// kernel writes, and the potential of the last GROWTH_FILLING kernel
+struct Growth {
+    sum: vec4<f32>,
+    written: vec4<bool>,
+    filling: f32,
+}
+
+fn add_growth(acc: ptr<function, Growth>, kernel: Kernel, U: f32, state: vec4<f32>) {
+    if kernel.growth == GROWTH_FILLING {
+        (*acc).filling = U;
+        return;
+    }
+    let d = kernel.destination;
+    (*acc).sum[d] += kernel.weight * growth(kernel, U, state[d], (*acc).filling);
+    (*acc).written[d] = true;
+}
+
+fn apply_growth(state: vec4<f32>, acc: Growth) -> vec4<f32> {
+    var new_state = state;
+    for (var c = 0u; c < params.channels; c++) {
+        if acc.written[c] {
+            new_state[c] = integrate(state[c], acc.sum[c]);
+        }
+    }
+    return new_state;
 }
 
 @compute @workgroup_size(8, 8, 1)
 fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
-    let new_state = compute_new_state(location);
-    let new_value = vec4<f32>(new_state);
+    if any(location >= grid.size) {
+        return;
+    }
+    let state = textureLoad(input, location, 0);
+    var acc = Growth(vec4<f32>(0.0), vec4<bool>(false), 0.0);
+    for (var k = 0u; k < params.kernel_count; k++) {
+        let kernel = kernels[k];
+        add_growth(&acc, kernel, potential(location, kernel), state);
+    }
+    store_state(location, apply_growth(state, acc));
+}
 
-    storageBarrier();
-    let color = vec4<f32>(new_value);
-    textureStore(texture, location, color);
+// FFT convolution: `fft_rows` transforms the rows of every channel,
+// `fft_convolve_columns` transforms the columns, multiplies them with the
+// spectrum of every kernel and transforms the products back, and `fft_update`
+// transforms the rows back into the potentials and applies the growth. Each
+// workgroup holds one row or column in workgroup memory, so both sides of the
+// grid must be powers of two no larger than FFT_MAX_SIZE.
+const FFT_MAX_SIZE = 1024u;
+const FFT_THREADS = 256u;
+
+var<workgroup> fft_data: array<vec2<f32>, FFT_MAX_SIZE>;
+
+fn complex_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+// position of element `i` of an `n` long sequence after the bit reversal permutation
+fn fft_index(i: u32, n: u32) -> u32 {
+    return reverseBits(i) >> (32u - firstTrailingBit(n));
+}
+
+// index of cell (x, y) of the `layer`th grid in `fft_scratch` or `kernel_spectra`
+fn grid_index(layer: u32, x: u32, y: u32) -> u32 {
+    let width = u32(grid.size.x);
+    return (layer * u32(grid.size.y) + y) * width + x;
+}
+
+// in place radix 2 FFT of the first `n` values of `fft_data`, which have to be
+// stored in bit reversed order; `direction` is -1 forward and 1 inverse
+fn fft_butterflies(n: u32, local: u32, direction: f32) {
+    for (var half = 1u; half < n; half <<= 1u) {
+        for (var b = local; b < n / 2u; b += FFT_THREADS) {
+            let k = b % half;
+            let i = (b / half) * 2u * half + k;
+            let angle = direction * 3.14159265 * f32(k) / f32(half);
+            let t = complex_mul(vec2<f32>(cos(angle), sin(angle)), fft_data[i + half]);
+            let a = fft_data[i];
+            fft_data[i] = a + t;
+            fft_data[i + half] = a - t;
+        }
+        workgroupBarrier();
+    }
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn fft_rows(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    let y = workgroup_id.y;
+    let n = u32(grid.size.x);
+    for (var c = 0u; c < params.channels; c++) {
+        for (var x = local; x < n; x += FFT_THREADS) {
+            let value = textureLoad(input, vec2<i32>(i32(x), i32(y)), 0)[c];
+            fft_data[fft_index(x, n)] = vec2<f32>(value, 0.0);
+        }
+        workgroupBarrier();
+        fft_butterflies(n, local, -1.0);
+        for (var x = local; x < n; x += FFT_THREADS) {
+            fft_scratch[grid_index(c, x, y)] = fft_data[x];
+        }
+        workgroupBarrier();
+    }
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn fft_convolve_columns(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    let x = workgroup_id.x;
+    let n = u32(grid.size.y);
+    for (var c = 0u; c < params.channels; c++) {
+        for (var y = local; y < n; y += FFT_THREADS) {
+            fft_data[fft_index(y, n)] = fft_scratch[grid_index(c, x, y)];
+        }
+        workgroupBarrier();
+        fft_butterflies(n, local, -1.0);
+        for (var y = local; y < n; y += FFT_THREADS) {
+            fft_scratch[grid_index(c, x, y)] = fft_data[y];
+        }
+        // the products below read transformed values other invocations wrote
+        workgroupBarrier();
+        storageBarrier();
+    }
+    for (var k = 0u; k < params.kernel_count; k++) {
+        let source = kernels[k].source;
+        for (var y = local; y < n; y += FFT_THREADS) {
+            let product = complex_mul(fft_scratch[grid_index(source, x, y)], kernel_spectra[grid_index(k, x, y)]);
+            fft_data[fft_index(y, n)] = product;
+        }
+        workgroupBarrier();
+        fft_butterflies(n, local, 1.0);
+        for (var y = local; y < n; y += FFT_THREADS) {
+            fft_scratch[grid_index(params.channels + k, x, y)] = fft_data[y];
+        }
+        workgroupBarrier();
+    }
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn fft_update(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    let y = workgroup_id.y;
+    let n = u32(grid.size.x);
+    // each invocation handles at most FFT_MAX_SIZE / FFT_THREADS cells of the row
+    var acc: array<Growth, 4>;
+    for (var x = local; x < n; x += FFT_THREADS) {
+        acc[x / FFT_THREADS] = Growth(vec4<f32>(0.0), vec4<bool>(false), 0.0);
+    }
+    for (var k = 0u; k < params.kernel_count; k++) {
+        let kernel = kernels[k];
+        for (var x = local; x < n; x += FFT_THREADS) {
+            fft_data[fft_index(x, n)] = fft_scratch[grid_index(params.channels + k, x, y)];
+        }
+        workgroupBarrier();
+        fft_butterflies(n, local, 1.0);
+        for (var x = local; x < n; x += FFT_THREADS) {
+            let state = textureLoad(input, vec2<i32>(i32(x), i32(y)), 0);
+            add_growth(&acc[x / FFT_THREADS], kernel, fft_data[x].x, state);
+        }
+        workgroupBarrier();
+    }
+    for (var x = local; x < n; x += FFT_THREADS) {
+        let location = vec2<i32>(i32(x), i32(y));
+        let state = textureLoad(input, location, 0);
+        store_state(location, apply_growth(state, acc[x / FFT_THREADS]));
+    }
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn display(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    if any(location >= grid.size) {
+        return;
+    }
+    let state = textureLoad(input, location, 0);
+    if params.channels == 1u {
+        store_display(location, vec4<f32>(state.x));
+    } else {
+        let used = vec3<f32>(vec3<u32>(0u, 1u, 2u) < vec3<u32>(params.channels));
+        let rgb = state.rgb * used;
+        store_display(location, vec4<f32>(rgb, max(rgb.r, max(rgb.g, rgb.b))));
+    }
 }
 
 // @fragment
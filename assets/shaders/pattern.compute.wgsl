@@ -0,0 +1,71 @@
+// Stamps a pattern into a state texture, scaled, rotated and centred on a
+// cell; `stamp` clears the cells outside of the pattern. Walls stay as they
+// are. The storage format of `output` is picked with a shader def.
+#import simulation::grid::is_solid
+
+@group(0) @binding(0) var pattern: texture_2d<f32>;
+#ifdef RGBA8UNORM
+@group(0) @binding(1) var output: texture_storage_2d<rgba8unorm, write>;
+#else ifdef RGBA16FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rgba16float, write>;
+#else ifdef RGBA32FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rgba32float, write>;
+#else ifdef R16FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<r16float, write>;
+#else ifdef RG32FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(1) var output: texture_storage_2d<r32float, write>;
+#endif
+
+struct Placement {
+    center: vec2<f32>,
+    scale: f32,
+    rotation: f32,
+}
+@group(0) @binding(2) var<uniform> placement: Placement;
+
+// the state being stamped over, only read by `stamp_over`
+@group(0) @binding(3) var input: texture_2d<f32>;
+
+// pattern texel landing on the cell at `location`, outside of the pattern
+// for cells it does not cover
+fn pattern_texel(location: vec2<i32>) -> vec2<i32> {
+    // undo the rotation, counterclockwise on screen where rows grow downwards
+    let offset = vec2<f32>(location) + 0.5 - placement.center;
+    let c = cos(placement.rotation);
+    let s = sin(placement.rotation);
+    let unrotated = vec2<f32>(c * offset.x - s * offset.y, s * offset.x + c * offset.y);
+    let pattern_size = vec2<f32>(textureDimensions(pattern));
+    return vec2<i32>(floor(unrotated / placement.scale + pattern_size * 0.5));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn stamp(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    if any(location >= vec2<i32>(textureDimensions(output))) {
+        return;
+    }
+    let texel = pattern_texel(location);
+    var value = vec4<f32>(0.0);
+    if all(texel >= vec2<i32>(0)) && all(texel < vec2<i32>(textureDimensions(pattern))) {
+        value = textureLoad(pattern, texel, 0);
+    }
+    textureStore(output, location, select(value, vec4<f32>(0.0), is_solid(location)));
+}
+
+// Like `stamp`, but only replaces the cells under the pattern and copies the
+// rest of the state through from `input`.
+@compute @workgroup_size(8, 8, 1)
+fn stamp_over(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    if any(location >= vec2<i32>(textureDimensions(output))) {
+        return;
+    }
+    let texel = pattern_texel(location);
+    if all(texel >= vec2<i32>(0)) && all(texel < vec2<i32>(textureDimensions(pattern))) && !is_solid(location) {
+        textureStore(output, location, textureLoad(pattern, texel, 0));
+    } else {
+        textureStore(output, location, textureLoad(input, location, 0));
+    }
+}
@@ -0,0 +1,40 @@
+// Scales a state texture into a texture of a different size with bilinear
+// filtering. The storage format of `output` is picked with a shader def.
+@group(0) @binding(0) var input: texture_2d<f32>;
+#ifdef RGBA8UNORM
+@group(0) @binding(1) var output: texture_storage_2d<rgba8unorm, write>;
+#else ifdef RGBA16FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rgba16float, write>;
+#else ifdef RGBA32FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rgba32float, write>;
+#else ifdef R16FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<r16float, write>;
+#else ifdef RG32FLOAT
+@group(0) @binding(1) var output: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(1) var output: texture_storage_2d<r32float, write>;
+#endif
+
+fn load_clamped(location: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(input));
+    return textureLoad(input, clamp(location, vec2<i32>(0), size - 1), 0);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn resample(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    let output_size = vec2<i32>(textureDimensions(output));
+    if any(location >= output_size) {
+        return;
+    }
+
+    // position of this cell's center in the input texture
+    let scale = vec2<f32>(textureDimensions(input)) / vec2<f32>(output_size);
+    let pos = (vec2<f32>(location) + 0.5) * scale - 0.5;
+    let pos00 = vec2<i32>(floor(pos));
+    let pos_fract = fract(pos);
+
+    let value0 = mix(load_clamped(pos00), load_clamped(pos00 + vec2<i32>(1, 0)), pos_fract.x);
+    let value1 = mix(load_clamped(pos00 + vec2<i32>(0, 1)), load_clamped(pos00 + vec2<i32>(1, 1)), pos_fract.x);
+    textureStore(output, location, mix(value0, value1, pos_fract.y));
+}
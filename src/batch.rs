@@ -0,0 +1,511 @@
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    prelude::*,
+    render::settings::WgpuSettings,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::flow_lenia::{FlowLenia, FlowLeniaComputePlugin};
+use crate::fluid::{Fluid, FluidComputePlugin};
+use crate::lenia::{cpu::CpuLenia, ActiveAnimal, AnimalLibrary, AnimalParams, Lenia, LeniaComputePlugin, LeniaParams};
+use crate::life::{Life, LifeComputePlugin, LifeRule};
+use crate::pattern::{InitialPattern, PatternAsset};
+use crate::simulation::{ComputeSimulation, GridSize, SimulationControl, SnapshotTaken, StateSnapshot, StateSnapshots};
+
+/// Exit status of a run that could not finish, e.g. because it diverged.
+const EXIT_FAILED: i32 = 1;
+/// Exit status of an unreadable or invalid config, as for bad flags.
+const EXIT_CONFIG: i32 = 2;
+
+/// A headless run, read from the JSON file given to `--headless`, e.g.
+///
+/// ```json
+/// {"simulation": "lenia", "steps": 2000, "snapshot_every": 100,
+///  "size": "256x256", "animal": "patterns/creatures.json", "output": "runs/orbium"}
+/// ```
+///
+/// Asset paths are relative to the `assets` folder, `output` to the working
+/// directory.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchConfig {
+    #[serde(default)]
+    pub simulation: BatchSimulation,
+    #[serde(default)]
+    pub backend: BatchBackend,
+    /// Update steps to run before exiting.
+    pub steps: u64,
+    /// Steps between snapshots; only the last state is written when missing.
+    #[serde(default)]
+    pub snapshot_every: Option<u64>,
+    /// Folder the snapshots and `stats.csv` are written to, created if missing.
+    #[serde(default = "default_output")]
+    pub output: PathBuf,
+    /// Grid size as `WIDTHxHEIGHT`, as for `--size`.
+    #[serde(default)]
+    pub size: Option<String>,
+    /// Steps per frame. The frame reaching a snapshot takes fewer steps, so
+    /// that the snapshots land exactly on the multiples of `snapshot_every`.
+    #[serde(default = "default_steps_per_frame")]
+    pub steps_per_frame: f32,
+    /// Wall clock time after which the run gives up.
+    #[serde(default = "default_timeout")]
+    pub timeout_seconds: f64,
+    /// Folder the asset paths are relative to. A relative folder is looked up
+    /// next to the config file first, then in the working directory.
+    #[serde(default = "default_assets")]
+    pub assets: PathBuf,
+    /// Asset path of a PNG, CSV or RLE pattern the simulation starts from.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Asset path of a Lenia JSON file whose creature `animal_index` sets the
+    /// rule and the initial pattern.
+    #[serde(default)]
+    pub animal: Option<String>,
+    #[serde(default)]
+    pub animal_index: usize,
+    /// A Lenia rule in the format of the reference implementation.
+    #[serde(default)]
+    pub params: Option<AnimalParams>,
+    /// The default rule of a Lenia family, as for `--family`.
+    #[serde(default)]
+    pub family: Option<String>,
+    /// The Life rule in B/S notation, as for `--rule`.
+    #[serde(default)]
+    pub rule: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchSimulation {
+    #[default]
+    Lenia,
+    FlowLenia,
+    Fluid,
+    Life,
+}
+
+/// Where the simulation runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchBackend {
+    /// The GPU if there is an adapter, otherwise the CPU for Lenia.
+    #[default]
+    Auto,
+    Gpu,
+    /// The reference implementation in [`crate::lenia::cpu`], Lenia only.
+    Cpu,
+}
+
+fn default_output() -> PathBuf {
+    PathBuf::from("batch")
+}
+
+fn default_assets() -> PathBuf {
+    PathBuf::from("assets")
+}
+
+fn default_steps_per_frame() -> f32 {
+    1.0
+}
+
+fn default_timeout() -> f64 {
+    600.0
+}
+
+impl BatchConfig {
+    pub fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        let config: Self = serde_json::from_slice(bytes).map_err(|err| err.to_string())?;
+        if config.steps == 0 {
+            return Err("`steps` must be at least 1".to_string());
+        }
+        if config.snapshot_every == Some(0) {
+            return Err("`snapshot_every` must be at least 1".to_string());
+        }
+        if config.steps_per_frame <= 0.0 {
+            return Err("`steps_per_frame` must be positive".to_string());
+        }
+        let lenia_only = [
+            ("animal", config.animal.is_some()),
+            ("params", config.params.is_some()),
+            ("family", config.family.is_some()),
+        ];
+        for (key, given) in lenia_only {
+            if given && config.simulation != BatchSimulation::Lenia {
+                return Err(format!("`{key}` only applies to the lenia simulation"));
+            }
+        }
+        if config.backend == BatchBackend::Cpu && config.simulation != BatchSimulation::Lenia {
+            return Err("only the lenia simulation runs on the cpu backend".to_string());
+        }
+        if config.rule.is_some() && config.simulation != BatchSimulation::Life {
+            return Err("`rule` only applies to the life simulation".to_string());
+        }
+        Ok(config)
+    }
+
+    /// The `assets` folder of the config file at `path`.
+    fn asset_folder(&self, path: &Path) -> PathBuf {
+        let beside_config = path.parent().unwrap_or(Path::new("")).join(&self.assets);
+        let folder = if beside_config.is_dir() {
+            beside_config
+        } else {
+            self.assets.clone()
+        };
+        std::path::absolute(&folder).unwrap_or(folder)
+    }
+}
+
+/// Runs the simulation of the config file at `path` without a window and
+/// returns the exit status: 0 once all steps ran, 1 if the run failed and 2
+/// if the config is invalid.
+///
+/// Every snapshot is written to `snapshot-<step>.csv` as the first channel of
+/// the first state texture, readable as a pattern, and adds a row to
+/// `stats.csv`.
+pub fn run(path: &str) -> i32 {
+    let config = match std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| BatchConfig::from_json(&bytes))
+    {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            return EXIT_CONFIG;
+        }
+    };
+    let grid: GridSize = match config.size.as_deref().map(str::parse).transpose() {
+        Ok(size) => size.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("{path}: size: {err}");
+            return EXIT_CONFIG;
+        }
+    };
+    let stats = match std::fs::create_dir_all(&config.output)
+        .and_then(|_| File::create(config.output.join("stats.csv")))
+        .map(BufWriter::new)
+        .and_then(|mut stats| writeln!(stats, "step,mass,live_cells,max").map(|_| stats))
+    {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("{}: {err}", config.output.display());
+            return EXIT_FAILED;
+        }
+    };
+
+    let assets = config.asset_folder(Path::new(path));
+
+    let status = Arc::new(AtomicI32::new(EXIT_FAILED));
+    let run = BatchRun {
+        steps: config.steps,
+        snapshot_every: config.snapshot_every,
+        next_snapshot: config.snapshot_every.unwrap_or(config.steps).min(config.steps),
+        step: 0,
+        steps_per_frame: config.steps_per_frame,
+        accumulator: 0.0,
+        scheduled: 0,
+        waiting: false,
+        output: config.output.clone(),
+        timeout: config.timeout_seconds,
+        stats,
+        finished: false,
+        status: status.clone(),
+    };
+    match config.backend {
+        BatchBackend::Cpu => return run_cpu(path, &config, &assets, grid.size, run),
+        BatchBackend::Auto if !gpu_available() => {
+            if config.simulation != BatchSimulation::Lenia {
+                eprintln!("no GPU adapter found to run {:?} on", config.simulation);
+                return EXIT_FAILED;
+            }
+            eprintln!("no GPU adapter found, running Lenia on the CPU");
+            return run_cpu(path, &config, &assets, grid.size, run);
+        }
+        _ => {}
+    }
+
+    let mut app = App::new();
+    app.insert_resource(grid).add_plugins((
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .set(AssetPlugin {
+                // an absolute path replaces the base path of the file reader
+                file_path: assets.to_string_lossy().into_owned(),
+                ..default()
+            })
+            .disable::<WinitPlugin>(),
+        ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+    ));
+    let setup = match config.simulation {
+        BatchSimulation::Lenia => setup_lenia(&mut app, &config),
+        BatchSimulation::FlowLenia => {
+            app.add_plugins(FlowLeniaComputePlugin);
+            add_recorder::<FlowLenia>(&mut app, &config)
+        }
+        BatchSimulation::Fluid => {
+            app.add_plugins(FluidComputePlugin);
+            add_recorder::<Fluid>(&mut app, &config)
+        }
+        BatchSimulation::Life => setup_life(&mut app, &config),
+    };
+    if let Err(err) = setup {
+        eprintln!("{path}: {err}");
+        return EXIT_CONFIG;
+    }
+    // `record` hands out the steps
+    app.world.resource_mut::<SimulationControl>().paused = true;
+    app.insert_resource(run);
+    app.run();
+    status.load(Ordering::SeqCst)
+}
+
+/// Whether wgpu finds an adapter on the backends the render plugin would try.
+fn gpu_available() -> bool {
+    let Some(backends) = WgpuSettings::default().backends else {
+        return false;
+    };
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { backends, ..default() });
+    bevy::tasks::block_on(instance.request_adapter(&default())).is_some()
+}
+
+/// Runs Lenia on the CPU, writing the same snapshots and stats.
+fn run_cpu(path: &str, config: &BatchConfig, assets: &Path, size: UVec2, mut run: BatchRun) -> i32 {
+    let mut lenia = match cpu_lenia(config, assets, size) {
+        Ok(lenia) => lenia,
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            return EXIT_CONFIG;
+        }
+    };
+    let start = Instant::now();
+    for step in 1.. {
+        lenia.step();
+        if step >= run.next_snapshot {
+            match run.record(&lenia.snapshot(step)) {
+                Ok(false) => {}
+                Ok(true) => break,
+                Err(err) => {
+                    eprintln!("{err}");
+                    return EXIT_FAILED;
+                }
+            }
+        }
+        if start.elapsed().as_secs_f64() > run.timeout {
+            eprintln!("timed out after {}s at step {step}", run.timeout);
+            return EXIT_FAILED;
+        }
+    }
+    0
+}
+
+/// The CPU counterpart of [`setup_lenia`], reading the assets from disk.
+fn cpu_lenia(config: &BatchConfig, assets: &Path, size: UVec2) -> Result<CpuLenia, String> {
+    let read = |path: &str| std::fs::read(assets.join(path)).map_err(|err| format!("{path}: {err}"));
+    let mut params = LeniaParams::default();
+    if let Some(family) = &config.family {
+        params = LeniaParams::family(family)?;
+    }
+    if let Some(animal_params) = &config.params {
+        params = animal_params.lenia_params().map_err(|err| err.to_string())?;
+    }
+    let mut pattern = match &config.pattern {
+        Some(path) => {
+            let extension = Path::new(path)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            Some(PatternAsset::from_bytes(&read(path)?, &extension).map_err(|err| format!("{path}: {err}"))?)
+        }
+        None => None,
+    };
+    if let Some(path) = &config.animal {
+        let library = AnimalLibrary::from_json(&read(path)?).map_err(|err| format!("{path}: {err}"))?;
+        let animal = library
+            .animals
+            .get(config.animal_index)
+            .ok_or_else(|| format!("{path}: no animal at index {}", config.animal_index))?;
+        params = animal.params().map_err(|err| format!("{}: {err}", animal.name))?;
+        pattern = Some(animal.pattern().map_err(|err| format!("{}: {err}", animal.name))?);
+    }
+    let mut lenia = CpuLenia::new(params, size);
+    match pattern {
+        Some(pattern) => lenia.stamp(&pattern, default()),
+        None => lenia.seed_random(),
+    }
+    Ok(lenia)
+}
+
+fn setup_lenia(app: &mut App, config: &BatchConfig) -> Result<(), String> {
+    app.add_plugins(LeniaComputePlugin::default());
+    if let Some(family) = &config.family {
+        app.insert_resource(LeniaParams::family(family)?);
+    }
+    if let Some(params) = &config.params {
+        app.insert_resource(params.lenia_params().map_err(|err| err.to_string())?);
+    }
+    if let Some(path) = &config.animal {
+        let library = app.world.resource::<AssetServer>().load(path.clone());
+        app.insert_resource(ActiveAnimal {
+            library,
+            index: config.animal_index,
+            placement: default(),
+        });
+    }
+    add_recorder::<Lenia>(app, config)
+}
+
+fn setup_life(app: &mut App, config: &BatchConfig) -> Result<(), String> {
+    let rule: LifeRule = config.rule.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+    app.add_plugins(LifeComputePlugin(rule));
+    add_recorder::<Life>(app, config)
+}
+
+fn add_recorder<S: ComputeSimulation>(app: &mut App, config: &BatchConfig) -> Result<(), String> {
+    if let Some(path) = &config.pattern {
+        let pattern = app.world.resource::<AssetServer>().load(path.clone());
+        app.insert_resource(InitialPattern::<S>::new(pattern, default()));
+    }
+    app.add_systems(Update, record::<S>);
+    Ok(())
+}
+
+/// Progress of the headless run.
+#[derive(Resource)]
+struct BatchRun {
+    steps: u64,
+    snapshot_every: Option<u64>,
+    /// Step the next snapshot is written at.
+    next_snapshot: u64,
+    /// Step of the latest snapshot.
+    step: u64,
+    steps_per_frame: f32,
+    /// Fractional steps carried over to the next frame.
+    accumulator: f32,
+    /// Steps handed to the simulation so far, corrected by every snapshot for
+    /// the steps it skipped while its pipelines compiled.
+    scheduled: u64,
+    /// Whether a snapshot was requested and has not arrived yet.
+    waiting: bool,
+    output: PathBuf,
+    timeout: f64,
+    stats: BufWriter<File>,
+    finished: bool,
+    /// Exit status handed back to [`run`] once the app exits.
+    status: Arc<AtomicI32>,
+}
+
+impl BatchRun {
+    /// Writes `snapshot` if it is due, returns whether the run is complete.
+    fn record(&mut self, snapshot: &StateSnapshot) -> Result<bool, String> {
+        self.step = snapshot.step;
+        if snapshot.step < self.next_snapshot {
+            return Ok(false);
+        }
+        let values: Vec<f32> = (0..snapshot.size.y)
+            .flat_map(|y| (0..snapshot.size.x).map(move |x| (x, y)))
+            .map(|(x, y)| snapshot.cell(x, y).x)
+            .collect();
+        let mass: f32 = values.iter().sum();
+        if !mass.is_finite() {
+            return Err(format!("the state diverged by step {}", snapshot.step));
+        }
+        let live_cells = values.iter().filter(|value| **value > 0.0).count();
+        let max = values.iter().copied().fold(0.0, f32::max);
+        writeln!(self.stats, "{},{mass},{live_cells},{max}", snapshot.step)
+            .and_then(|_| self.stats.flush())
+            .map_err(|err| format!("stats.csv: {err}"))?;
+
+        let name = format!("snapshot-{:06}.csv", snapshot.step);
+        let rows: Vec<String> = values
+            .chunks(snapshot.size.x as usize)
+            .map(|row| row.iter().map(f32::to_string).collect::<Vec<_>>().join(","))
+            .collect();
+        std::fs::write(self.output.join(&name), rows.join("\n") + "\n").map_err(|err| format!("{name}: {err}"))?;
+        info!("step {}: mass {mass}, {live_cells} live cells", snapshot.step);
+
+        if snapshot.step >= self.steps {
+            return Ok(true);
+        }
+        self.next_snapshot = match self.snapshot_every {
+            Some(every) => (snapshot.step / every + 1) * every,
+            None => self.steps,
+        }
+        .min(self.steps);
+        Ok(false)
+    }
+
+    /// Steps to take this frame, up to the next snapshot.
+    fn frame_steps(&mut self) -> u32 {
+        self.accumulator += self.steps_per_frame;
+        let steps = self.accumulator.floor();
+        self.accumulator -= steps;
+        (steps as u64).min(self.next_snapshot.saturating_sub(self.scheduled)) as u32
+    }
+
+    fn finish(&mut self, status: i32, exit: &mut EventWriter<AppExit>) {
+        self.finished = true;
+        self.status.store(status, Ordering::SeqCst);
+        exit.send(AppExit);
+    }
+}
+
+/// Steps the simulation up to the next snapshot that is due, then requests
+/// it and waits for it to arrive before stepping on.
+fn record<S: ComputeSimulation>(
+    mut run: ResMut<BatchRun>,
+    mut control: ResMut<SimulationControl>,
+    mut snapshots: ResMut<StateSnapshots<S>>,
+    mut taken: EventReader<SnapshotTaken<S>>,
+    time: Res<Time<Real>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if run.finished {
+        return;
+    }
+    for event in taken.read() {
+        // short of the snapshot if steps were skipped, which are taken again
+        run.scheduled = event.snapshot.step;
+        run.waiting = false;
+        match run.record(&event.snapshot) {
+            Ok(false) => {}
+            Ok(true) => {
+                info!("finished {} steps", event.snapshot.step);
+                return run.finish(0, &mut exit);
+            }
+            Err(err) => {
+                error!("{err}");
+                return run.finish(EXIT_FAILED, &mut exit);
+            }
+        }
+    }
+    if !run.waiting {
+        let steps = run.frame_steps();
+        control.step_by(steps);
+        run.scheduled += steps as u64;
+        if run.scheduled >= run.next_snapshot {
+            snapshots.request();
+            run.waiting = true;
+        }
+    }
+    if time.elapsed_seconds_f64() > run.timeout {
+        error!("timed out after {}s at step {}", run.timeout, run.step);
+        run.finish(EXIT_FAILED, &mut exit);
+    }
+}
@@ -0,0 +1,212 @@
+use bevy::{
+    prelude::*,
+    render::extract_resource::ExtractResource,
+    window::PrimaryWindow,
+};
+use std::marker::PhantomData;
+
+use crate::simulation::{ComputeSimulation, GridSize, SimulationActive};
+pub(crate) use uniforms::BrushDab;
+
+/// What a [`Brush`] does to the cells under it, see `brush.compute.wgsl`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrushMode {
+    /// Adds `strength` to every channel, up to 1 in simulations whose state
+    /// lies in `[0, 1]`.
+    #[default]
+    Add = 0,
+    /// Fades the cells towards zero, fully at a `strength` of 1.
+    Erase = 1,
+    /// Replaces every channel with `strength`.
+    Set = 2,
+    /// Replaces the cells with uniform noise up to `strength`.
+    Noise = 3,
+}
+
+impl BrushMode {
+    fn next(self) -> Self {
+        match self {
+            BrushMode::Add => BrushMode::Erase,
+            BrushMode::Erase => BrushMode::Set,
+            BrushMode::Set => BrushMode::Noise,
+            BrushMode::Noise => BrushMode::Add,
+        }
+    }
+}
+
+/// Settings of the mouse brush painting into the first state texture.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Brush {
+    pub mode: BrushMode,
+    /// Radius in grid cells.
+    pub radius: f32,
+    pub strength: f32,
+    /// Fraction of the radius over which the brush fades out towards its
+    /// edge, 0 for a hard edge.
+    pub falloff: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            mode: BrushMode::Add,
+            radius: 8.0,
+            strength: 0.5,
+            falloff: 0.5,
+        }
+    }
+}
+
+mod uniforms {
+    // see `uniforms` in simulation.rs
+    #![allow(dead_code)]
+
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    /// A single brush stamp, as `brush.compute.wgsl` reads it.
+    #[derive(Clone, Copy, Debug, ShaderType)]
+    pub struct BrushDab {
+        /// Centre in grid cells from the top left corner.
+        pub center: Vec2,
+        pub radius: f32,
+        pub strength: f32,
+        pub falloff: f32,
+        pub mode: u32,
+        pub seed: u32,
+    }
+}
+
+/// Dabs painted into `S` this frame.
+#[derive(Resource)]
+pub(crate) struct BrushStrokes<S: ComputeSimulation> {
+    pub dabs: Vec<BrushDab>,
+    /// Bumped with every batch, so that each is painted once.
+    pub generation: u32,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Default for BrushStrokes<S> {
+    fn default() -> Self {
+        Self {
+            dabs: Vec::new(),
+            generation: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> ExtractResource for BrushStrokes<S> {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        Self {
+            dabs: source.dabs.clone(),
+            generation: source.generation,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Paints into the state of `S` with the mouse: ctrl + left button applies
+/// the [`Brush`], ctrl + right button erases. B cycles through the modes, the
+/// bracket keys shrink and grow the brush.
+pub struct BrushPlugin<S: ComputeSimulation>(PhantomData<fn() -> S>);
+
+impl<S: ComputeSimulation> Default for BrushPlugin<S> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<S: ComputeSimulation> Plugin for BrushPlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, paint_cells::<S>);
+        if !app.is_plugin_added::<BrushSettingsPlugin>() {
+            app.add_plugins(BrushSettingsPlugin);
+        }
+    }
+}
+
+/// The [`Brush`] shared by every [`BrushPlugin`] and its key bindings.
+pub struct BrushSettingsPlugin;
+
+impl Plugin for BrushSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Brush>().add_systems(Update, adjust_brush);
+    }
+}
+
+fn adjust_brush(keys: Res<ButtonInput<KeyCode>>, mut brush: ResMut<Brush>) {
+    if keys.just_pressed(KeyCode::KeyB) {
+        brush.mode = brush.mode.next();
+        info!("brush mode: {:?}", brush.mode);
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        brush.radius = (brush.radius / 1.25).max(1.0);
+        info!("brush radius: {:.1}", brush.radius);
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        brush.radius *= 1.25;
+        info!("brush radius: {:.1}", brush.radius);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn paint_cells<S: ComputeSimulation>(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    interactions: Query<&Interaction>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    grid: Res<GridSize>,
+    active: Res<SimulationActive<S>>,
+    brush: Res<Brush>,
+    mut last_cell: Local<Option<Vec2>>,
+    mut seed: Local<u32>,
+    mut strokes: ResMut<BrushStrokes<S>>,
+) {
+    let mode = if !active.active || !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        None
+    } else if buttons.pressed(MouseButton::Left) {
+        Some(brush.mode)
+    } else if buttons.pressed(MouseButton::Right) {
+        Some(BrushMode::Erase)
+    } else {
+        None
+    };
+    // strokes starting on the UI are not meant for the grid
+    let over_ui = interactions.iter().any(|interaction| *interaction != Interaction::None);
+    let cell = match (windows.get_single(), cameras.get_single()) {
+        (Ok(window), Ok((camera, camera_transform))) if !over_ui || last_cell.is_some() => {
+            grid.cursor_cell(window, camera, camera_transform)
+        }
+        _ => None,
+    };
+    let (Some(mode), Some(cell)) = (mode, cell) else {
+        *last_cell = None;
+        if !strokes.dabs.is_empty() {
+            strokes.dabs.clear();
+        }
+        return;
+    };
+
+    // space the dabs along the drag so that fast strokes stay continuous
+    let from = last_cell.unwrap_or(cell);
+    let spacing = (brush.radius * 0.5).max(1.0);
+    let steps = ((cell - from).length() / spacing).ceil().max(1.0) as u32;
+    strokes.dabs = (1..=steps)
+        .map(|step| {
+            *seed = seed.wrapping_add(1);
+            BrushDab {
+                center: from.lerp(cell, step as f32 / steps as f32),
+                radius: brush.radius,
+                strength: brush.strength,
+                falloff: brush.falloff,
+                mode: mode as u32,
+                seed: *seed,
+            }
+        })
+        .collect();
+    strokes.generation += 1;
+    *last_cell = Some(cell);
+}
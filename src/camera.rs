@@ -0,0 +1,118 @@
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    window::PrimaryWindow,
+};
+
+use crate::simulation::GridSize;
+
+/// Zoom factor per line of mouse wheel scrolling.
+const ZOOM_STEP: f32 = 1.1;
+/// Limits of the projection scale, in world units per screen pixel.
+const MIN_SCALE: f32 = 1.0 / 64.0;
+const MAX_SCALE: f32 = 64.0;
+
+/// Spawns the 2D camera and lets it be moved around the grid: the mouse wheel
+/// zooms around the cursor, dragging with the right or middle button pans and
+/// F fits the whole grid into the window.
+///
+/// The sprites keep one world unit per cell, so
+/// [`GridSize::cursor_cell`] maps the cursor through the same transform.
+pub struct CameraControlPlugin;
+
+impl Plugin for CameraControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_camera)
+            .add_systems(Update, (zoom_camera, pan_camera, fit_camera));
+    }
+}
+
+fn setup_camera(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+}
+
+fn zoom_camera(
+    mut wheel: EventReader<MouseWheel>,
+    interactions: Query<&Interaction>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(&Camera, &GlobalTransform, &mut Transform, &mut OrthographicProjection)>,
+) {
+    let lines: f32 = wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 100.0,
+        })
+        .sum();
+    if lines == 0.0 || interactions.iter().any(|interaction| *interaction != Interaction::None) {
+        return;
+    }
+    let (Ok(window), Ok((camera, camera_transform, mut transform, mut projection))) =
+        (windows.get_single(), cameras.get_single_mut())
+    else {
+        return;
+    };
+    let scale = (projection.scale * ZOOM_STEP.powf(-lines)).clamp(MIN_SCALE, MAX_SCALE);
+    // keep the point under the cursor in place
+    if let Some(anchor) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+    {
+        let centre = transform.translation.truncate();
+        let centre = anchor + (centre - anchor) * scale / projection.scale;
+        transform.translation = centre.extend(transform.translation.z);
+    }
+    projection.scale = scale;
+}
+
+/// Pans by the change in logical cursor position, so the cell under the
+/// cursor stays under it during a drag. `last` holds the cursor position of
+/// the previous frame.
+fn pan_camera(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(&mut Transform, &OrthographicProjection), With<Camera>>,
+    mut last: Local<Option<Vec2>>,
+) {
+    let cursor = windows.get_single().ok().and_then(Window::cursor_position);
+    let previous = std::mem::replace(&mut *last, cursor);
+    // shift and ctrl + right button erase walls and cells instead
+    let modifiers = [KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight];
+    let panning = buttons.pressed(MouseButton::Middle)
+        || (buttons.pressed(MouseButton::Right) && !keys.any_pressed(modifiers));
+    let (true, Some(previous), Some(cursor)) = (panning, previous, cursor) else {
+        return;
+    };
+    let delta = cursor - previous;
+    if delta == Vec2::ZERO {
+        return;
+    }
+    let Ok((mut transform, projection)) = cameras.get_single_mut() else {
+        return;
+    };
+    // screen y grows downwards, world y upwards
+    transform.translation.x -= delta.x * projection.scale;
+    transform.translation.y += delta.y * projection.scale;
+}
+
+/// Centres the grid and scales it to fit the window when pressing F
+fn fit_camera(
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    grid: Res<GridSize>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+    let (Ok(window), Ok((mut transform, mut projection))) = (windows.get_single(), cameras.get_single_mut())
+    else {
+        return;
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let scale = grid.size.as_vec2() / window_size;
+    projection.scale = scale.max_element().clamp(MIN_SCALE, MAX_SCALE);
+    transform.translation.x = 0.0;
+    transform.translation.y = 0.0;
+}
@@ -2,307 +2,257 @@ use bevy::{
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
-        render_asset::RenderAssetUsages,
-        render_asset::RenderAssets,
-        render_graph::{self, RenderGraph, RenderLabel},
         render_resource::*,
-        renderer::{RenderContext, RenderDevice},
+        renderer::{RenderDevice, RenderQueue},
         Render, RenderApp, RenderSet,
     },
+    window::PrimaryWindow,
 };
 
-pub const SIZE: (u32, u32) = (600, 400);
-const WORKGROUP_SIZE: u32 = 8;
-use std::borrow::Cow;
+use crate::simulation::{
+    Boundary, ComputeSimulation, ComputeSimulationPlugin, GridSize, SimulationActive, SimulationSteps, UpdatePass,
+    Workgroups,
+};
+pub use uniforms::FluidSplat;
+use uniforms::FluidSplats;
 
-pub struct FluidComputePlugin;
+/// Most splats injected in a single frame, the length of the array in `fluid.compute.wgsl`.
+const MAX_SPLATS: usize = 16;
 
-#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
-struct FluidLabel;
+/// Runs the [`Fluid`] simulation. Dragging with the left mouse button pushes
+/// the fluid along the drag and drops dye into it.
+pub struct FluidComputePlugin;
 
 impl Plugin for FluidComputePlugin {
     fn build(&self, app: &mut App) {
-        // Extract the fluid image resource from the main world into the render world
-        // for operation on by the compute shader and display on the sprite.
-        app
-            .add_systems(Startup, setup)
-            .add_plugins(ExtractResourcePlugin::<FluidImage>::default());
-        let render_app = app.sub_app_mut(RenderApp);
-        render_app
-            .add_systems(
-            Render,
-            prepare_bind_group.in_set(RenderSet::PrepareBindGroups),
-        );
-
-        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
-        render_graph.add_node(FluidLabel, FluidNode::default());
-        render_graph.add_node_edge(FluidLabel, bevy::render::graph::CameraDriverLabel,
-        );
+        app.add_plugins(ComputeSimulationPlugin(Fluid::default()))
+            .init_resource::<FluidStrokes>()
+            .add_plugins(ExtractResourcePlugin::<FluidStrokes>::default())
+            .add_systems(Update, drag_splats);
+        app.sub_app_mut(RenderApp)
+            .add_systems(Render, prepare_splats.in_set(RenderSet::PrepareResources));
     }
+}
 
-    fn finish(&self, app: &mut App) {
-        let render_app = app.sub_app_mut(RenderApp);
-        render_app.init_resource::<FluidPipeline>();
-    }
+/// Incompressible fluid advecting a colour field. The state textures are the
+/// colour, the two velocity components and the pressure, in that order.
+#[derive(Resource, Clone, Default)]
+pub struct Fluid {
+    /// [`FluidSplats`] injected by the next update step, created by
+    /// `prepare_splats` in the render world.
+    splats: Option<Buffer>,
 }
 
-fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
-    let mut color_img = Image::new_fill(
-        Extent3d {
-            width: SIZE.0,
-            height: SIZE.1,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &[0, 0, 0, 255],
-        TextureFormat::Rgba8Unorm,
-        RenderAssetUsages::RENDER_WORLD,
-    );
-    color_img.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-    let color_img = images.add(color_img);
+impl ComputeSimulation for Fluid {
+    type Params = FluidParams;
+    type Bindings = FluidBindings;
 
-    let mut velocity_x_img = Image::new_fill(
-        Extent3d {
-            width: SIZE.0,
-            height: SIZE.1,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &[0, 0, 0, 0],
-        TextureFormat::R32Float,
-        RenderAssetUsages::RENDER_WORLD,
-    );
-    velocity_x_img.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-    let velocity_x_img = images.add(velocity_x_img);
+    const NAME: &'static str = "fluid";
+    const SHADER: &'static str = "shaders/fluid.compute.wgsl";
 
-    let mut velocity_y_img = Image::new_fill(
-        Extent3d {
-            width: SIZE.0,
-            height: SIZE.1,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &[0, 0, 0, 0],
-        TextureFormat::R32Float,
-        RenderAssetUsages::RENDER_WORLD,
-    );
-    velocity_y_img.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-    let velocity_y_img = images.add(velocity_y_img);
+    fn textures(&self, _params: &FluidParams) -> Vec<TextureFormat> {
+        vec![
+            TextureFormat::Rgba8Unorm,
+            TextureFormat::R32Float,
+            TextureFormat::R32Float,
+            TextureFormat::R32Float,
+        ]
+    }
 
-    let mut pressure_img = Image::new_fill(
-        Extent3d {
-            width: SIZE.0,
-            height: SIZE.1,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &[0, 0, 0, 0],
-        TextureFormat::R32Float,
-        RenderAssetUsages::RENDER_WORLD,
-    );
-    pressure_img.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-    let pressure_img = images.add(pressure_img);
+    fn boundary(params: &FluidParams) -> Boundary {
+        params.boundary
+    }
 
-    commands.spawn(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(SIZE.0 as f32, SIZE.1 as f32)),
-            ..default()
-        },
-        texture: color_img.clone(),
-        // texture: velocity_x_img.clone(),
-        // texture: pressure_img.clone(),
-        ..default()
-    });
-    commands.spawn(Camera2dBundle::default());
-    commands.insert_resource(FluidImage{ color_img, velocity_x_img, velocity_y_img, pressure_img });
-}
+    fn set_boundary(params: &mut FluidParams, boundary: Boundary) {
+        params.boundary = boundary;
+    }
 
+    fn update_passes(&self, params: &FluidParams, _size: UVec2) -> Vec<UpdatePass> {
+        vec![
+            UpdatePass::new("update").writes(&[0, 1, 2]),
+            UpdatePass::new("consume_splats")
+                .workgroups(Workgroups::Single)
+                .bindings_only(),
+            UpdatePass::new("update_pressure")
+                .repeat(params.pressure_iterations)
+                .writes(&[3]),
+        ]
+    }
 
-#[derive(Resource, Clone, Deref, ExtractResource, AsBindGroup)]
-struct FluidImage {
-    #[deref]
-    #[storage_texture(0, image_format = Rgba8Unorm, access = ReadWrite)]
-    color_img: Handle<Image>,
-    #[storage_texture(1, image_format = R32Float, access = ReadWrite)]
-    velocity_x_img: Handle<Image>,
-    #[storage_texture(2, image_format = R32Float, access = ReadWrite)]
-    velocity_y_img: Handle<Image>,
-    #[storage_texture(3, image_format = R32Float, access = ReadWrite)]
-    pressure_img: Handle<Image>,
+    fn bindings(&mut self, params: &FluidParams, _size: UVec2) -> FluidBindings {
+        FluidBindings {
+            density: params.density,
+            splats: self
+                .splats
+                .clone()
+                .expect("the splat buffer is created before the bind groups"),
+        }
+    }
 }
 
-#[derive(Resource)]
-struct FluidImageBindGroup(BindGroup);
+mod uniforms {
+    // see `uniforms` in simulation.rs
+    #![allow(dead_code)]
 
-fn prepare_bind_group(
-    mut commands: Commands,
-    pipeline: Res<FluidPipeline>,
-    gpu_images: Res<RenderAssets<Image>>,
-    fluid_image: Res<FluidImage>,
-    render_device: Res<RenderDevice>,
-) {
-    let color_view = gpu_images.get(&fluid_image.color_img).unwrap();
-    let velocity_x_view = gpu_images.get(&fluid_image.velocity_x_img).unwrap();
-    let velocity_y_view = gpu_images.get(&fluid_image.velocity_y_img).unwrap();
-    let pressure_view = gpu_images.get(&fluid_image.pressure_img).unwrap();
-
-    let bind_group = render_device.create_bind_group(
-        None,
-        &pipeline.texture_bind_group_layout,
-        &BindGroupEntries::with_indices((
-            (0, &color_view.texture_view),
-            (1, &velocity_x_view.texture_view),
-            (2, &velocity_y_view.texture_view),
-            (3, &pressure_view.texture_view),
-        )),
-    );
-    commands.insert_resource(FluidImageBindGroup(bind_group));
-}
+    use bevy::{prelude::*, render::render_resource::ShaderType};
 
-#[derive(Resource)]
-pub struct FluidPipeline {
-    texture_bind_group_layout: BindGroupLayout,
-    init_pipeline: CachedComputePipelineId,
-    update_pressure_pipeline: CachedComputePipelineId,
-    update_pipeline: CachedComputePipelineId,
-    // render_pipeline: CachedRenderPipelineId,
-}
+    use super::MAX_SPLATS;
 
-// パイプラインの初期化を定義
-impl FromWorld for FluidPipeline {
-fn from_world(world: &mut World) -> Self {
-        let render_device = world.resource::<RenderDevice>();
-        let texture_bind_group_layout = FluidImage::bind_group_layout(render_device);
-        let shader = world
-            .resource::<AssetServer>()
-            .load("shaders/fluid.compute.wgsl");
-        let pipeline_cache = world.resource::<PipelineCache>();
-        let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from("init"),
-        });
-        let update_pressure_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from("update_pressure"),
-        });
-        let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader,
-            shader_defs: vec![],
-            entry_point: Cow::from("update"),
-        });
+    /// Velocity and dye injected around a cell by the next update, with a
+    /// Gaussian falloff.
+    #[derive(Clone, Copy, Debug, Default, ShaderType)]
+    pub struct FluidSplat {
+        /// Centre in grid cells from the top left corner.
+        pub position: Vec2,
+        /// Velocity added at the centre, in cells per update.
+        pub force: Vec2,
+        /// Dye blended in at the centre.
+        pub color: Vec4,
+        /// Distance in cells at which the splat has fallen off to `1/e`.
+        pub radius: f32,
+    }
 
-        FluidPipeline {
-            texture_bind_group_layout,
-            init_pipeline,
-            update_pressure_pipeline,
-            update_pipeline,
-            // render_pipeline,
-        }
+    /// The splat buffer, as `fluid.compute.wgsl` reads it.
+    #[derive(Clone, Copy, Debug, ShaderType)]
+    pub struct FluidSplats {
+        pub count: u32,
+        pub splats: [FluidSplat; MAX_SPLATS],
     }
 }
 
-enum FluidState {
-    Loading,
-    Init,
-    Update,
+/// Splats the mouse drag injected this frame.
+#[derive(Resource, Clone, Default, ExtractResource)]
+struct FluidStrokes {
+    splats: Vec<FluidSplat>,
+    /// Bumped with every batch, so that each is injected once.
+    generation: u32,
 }
 
-struct FluidNode {
-    state: FluidState,
+#[derive(Resource, Clone, Debug, ExtractResource)]
+pub struct FluidParams {
+    pub density: f32,
+    /// Jacobi iterations of the pressure solve per frame.
+    pub pressure_iterations: u32,
+    /// [`Boundary::Reflecting`] and [`Boundary::Zero`] act as free slip and
+    /// no slip walls, making the grid a closed container.
+    pub boundary: Boundary,
+    /// Radius of the splats the mouse drag injects, in cells.
+    pub splat_radius: f32,
+    /// Velocity each splat injects per cell the cursor moved in a frame.
+    pub splat_force: f32,
 }
 
-impl Default for FluidNode {
+impl Default for FluidParams {
     fn default() -> Self {
         Self {
-            state: FluidState::Loading,
+            density: 1.0,
+            pressure_iterations: 100,
+            boundary: Boundary::Reflecting,
+            splat_radius: 12.0,
+            splat_force: 0.2,
         }
     }
 }
 
-impl render_graph::Node for FluidNode {
-    fn update(&mut self, world: &mut World) {
-        let pipeline = world.resource::<FluidPipeline>();
-        let pipeline_cache = world.resource::<PipelineCache>();
+#[derive(AsBindGroup)]
+pub struct FluidBindings {
+    #[uniform(0)]
+    density: f32,
+    #[storage(1, visibility(compute), buffer)]
+    splats: Buffer,
+}
 
-        // if the corresponding pipeline has loaded, transition to the next stage
-        match self.state {
-            FluidState::Loading => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.init_pipeline)
-                {
-                    self.state = FluidState::Init;
-                }
-            }
-            FluidState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
-                {
-                    self.state = FluidState::Update;
-                }
-            }
-            FluidState::Update => {}
-        }
+/// Writes the splats dragged in since the last step into the splat buffer,
+/// which the `consume_splats` pass empties again after the first step. The
+/// splats wait for frames that take no steps, and those beyond [`MAX_SPLATS`]
+/// for the next frame.
+#[allow(clippy::too_many_arguments)]
+fn prepare_splats(
+    mut fluid: ResMut<Fluid>,
+    strokes: Res<FluidStrokes>,
+    steps: Option<Res<SimulationSteps>>,
+    active: Option<Res<SimulationActive<Fluid>>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut pending: Local<Vec<FluidSplat>>,
+    mut generation: Local<u32>,
+) {
+    if strokes.generation != *generation {
+        *generation = strokes.generation;
+        pending.extend_from_slice(&strokes.splats);
+    }
+    if fluid.splats.is_none() {
+        fluid.splats = Some(render_device.create_buffer(&BufferDescriptor {
+            label: Some("fluid_splats"),
+            size: FluidSplats::min_size().get(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
     }
 
-    fn run(
-        &self,
-        _graph: &mut render_graph::RenderGraphContext,
-        render_context: &mut RenderContext,
-        world: &World,
-    ) -> Result<(), render_graph::NodeRunError> {
-        let texture_bind_group = &world.resource::<FluidImageBindGroup>().0;
-        let pipeline_cache = world.resource::<PipelineCache>();
-        let pipeline = world.resource::<FluidPipeline>();
-
-        let mut pass = render_context
-            .command_encoder()
-            .begin_compute_pass(&ComputePassDescriptor::default());
-
-        pass.set_bind_group(0, texture_bind_group, &[]);
-
-        // select the pipeline based on the current state
-        match self.state {
-            FluidState::Loading => {}
-            FluidState::Init => {
-                let init_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.init_pipeline)
-                    .unwrap();
-                pass.set_pipeline(init_pipeline);
-                pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
-            }
-            FluidState::Update => {
-                let update_pressure_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.update_pressure_pipeline)
-                    .unwrap();
-                let update_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.update_pipeline)
-                    .unwrap();
-
+    let stepping = steps.map_or(1, |steps| steps.0) > 0 && active.is_none_or(|active| active.active);
+    if !stepping || pending.is_empty() {
+        return;
+    }
+    let mut splats = FluidSplats {
+        count: pending.len().min(MAX_SPLATS) as u32,
+        splats: [FluidSplat::default(); MAX_SPLATS],
+    };
+    for (splat, pending) in splats.splats.iter_mut().zip(pending.drain(..splats.count as usize)) {
+        *splat = pending;
+    }
+    let mut buffer = encase::StorageBuffer::new(Vec::new());
+    buffer.write(&splats).unwrap();
+    render_queue.write_buffer(fluid.splats.as_ref().unwrap(), 0, buffer.as_ref());
+}
 
-                pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
-                pass.set_pipeline(update_pressure_pipeline);
-                for _ in 0..100{
-                    pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
-                }
-            }
+/// Turns a left button drag over the grid into splats along the path of the
+/// cursor, tinted with a hue that cycles over time.
+#[allow(clippy::too_many_arguments)]
+fn drag_splats(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    interactions: Query<&Interaction>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    grid: Res<GridSize>,
+    active: Res<SimulationActive<Fluid>>,
+    time: Res<Time>,
+    mut last_cell: Local<Option<Vec2>>,
+    params: Res<FluidParams>,
+    mut strokes: ResMut<FluidStrokes>,
+) {
+    // modified clicks belong to the brushes, clicks on the UI to the UI
+    let modifiers = [KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight];
+    let over_ui = interactions.iter().any(|interaction| *interaction != Interaction::None);
+    let dragging = active.active && buttons.pressed(MouseButton::Left) && !keys.any_pressed(modifiers);
+    let cell = match (windows.get_single(), cameras.get_single()) {
+        (Ok(window), Ok((camera, camera_transform))) if dragging && (!over_ui || last_cell.is_some()) => {
+            grid.cursor_cell(window, camera, camera_transform)
         }
-
-        Ok(())
+        _ => None,
+    };
+    let from = std::mem::replace(&mut *last_cell, cell);
+    let (Some(from), Some(cell)) = (from, cell) else {
+        return;
+    };
+    let drag = cell - from;
+    if drag == Vec2::ZERO {
+        return;
     }
+
+    // spread the drag over several splats so that fast strokes stay continuous
+    let radius = params.splat_radius;
+    let steps = ((drag.length() / radius).ceil() as usize).clamp(1, MAX_SPLATS);
+    let force = drag * params.splat_force;
+    let color = Color::hsl((time.elapsed_seconds() * 60.0) % 360.0, 1.0, 0.5).rgba_to_vec4();
+    strokes.splats = (1..=steps)
+        .map(|step| FluidSplat {
+            position: from.lerp(cell, step as f32 / steps as f32),
+            force,
+            color,
+            radius,
+        })
+        .collect();
+    strokes.generation += 1;
 }
@@ -1,227 +1,595 @@
 use bevy::{
     prelude::*,
     render::{
-        extract_resource::{ExtractResource, ExtractResourcePlugin},
-        render_asset::RenderAssetUsages,
-        render_asset::RenderAssets,
-        render_graph::{self, RenderGraph, RenderLabel},
-        render_resource::*,
-        renderer::{RenderContext, RenderDevice},
+        extract_resource::ExtractResource,
+        render_resource::{encase::internal::WriteInto, *},
+        renderer::{RenderDevice, RenderQueue},
+        settings::WgpuLimits,
         Render, RenderApp, RenderSet,
     },
 };
+use std::str::FromStr;
 
-pub const SIZE: (u32, u32) = (600, 400);
-const WORKGROUP_SIZE: u32 = 8;
-use std::borrow::Cow;
+use crate::simulation::{
+    storage_format_def, Boundary, ComputeSimulation, ComputeSimulationPlugin, SimulationActive, SimulationImages,
+    UpdatePass, Workgroups,
+};
+
+mod animal;
+pub mod cpu;
+pub mod families;
+mod kernel;
 
-pub struct LeniaComputePlugin;
+pub use animal::{ActiveAnimal, AnimalLibrary, AnimalParams};
+use kernel::KernelCache;
+use uniforms::{KernelUniform, LeniaUniform};
 
-#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
-struct LeniaLabel;
+/// Largest grid side the FFT passes handle, bounded by the workgroup memory
+/// holding one row or column in `lenia.compute.wgsl`. The grid is further
+/// bounded by the storage buffer limit of the device: the kernel spectra take
+/// 8 bytes per cell and kernel, the FFT scratch memory 8 bytes per cell and
+/// channel or kernel. Grids whose buffers would exceed it fall back to
+/// [`Convolution::Direct`].
+const FFT_MAX_SIZE: u32 = 1024;
+
+#[derive(Default)]
+pub struct LeniaComputePlugin(pub Lenia);
 
 impl Plugin for LeniaComputePlugin {
     fn build(&self, app: &mut App) {
-        // Extract the lenia image resource from the main world into the render world
-        // for operation on by the compute shader and display on the sprite.
-        app
-            .add_systems(Startup, setup)
-            .add_plugins(ExtractResourcePlugin::<LeniaImage>::default());
-        let render_app = app.sub_app_mut(RenderApp);
-        render_app
-            .add_systems(
-            Render,
-            prepare_bind_group.in_set(RenderSet::PrepareBindGroups),
-        );
-
-        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
-        render_graph.add_node(LeniaLabel, LeniaNode::default());
-        render_graph.add_node_edge(LeniaLabel, bevy::render::graph::CameraDriverLabel,
-        );
+        app.add_plugins(ComputeSimulationPlugin(self.0.clone()))
+            .init_asset::<AnimalLibrary>()
+            .init_asset_loader::<animal::AnimalLoader>()
+            .add_systems(Update, (animal::apply_animal, animal::export_animal, cycle_integrator));
+        app.sub_app_mut(RenderApp)
+            .add_systems(Render, prepare_kernel_buffers.in_set(RenderSet::PrepareResources));
     }
 
     fn finish(&self, app: &mut App) {
-        let render_app = app.sub_app_mut(RenderApp);
-        render_app.init_resource::<LeniaPipeline>();
-    }
-}
-
-fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
-    let mut image = Image::new_fill(
-        Extent3d {
-            width: SIZE.0,
-            height: SIZE.1,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &[0, 0, 0, 255],
-        TextureFormat::Rgba8Unorm,
-        RenderAssetUsages::RENDER_WORLD,
-    );
-    image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-    let image = images.add(image);
-
-    commands.spawn(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(SIZE.0 as f32, SIZE.1 as f32)),
+        let render_world = &mut app.sub_app_mut(RenderApp).world;
+        let limit = render_world.resource::<RenderDevice>().limits().max_storage_buffer_binding_size;
+        render_world.resource_mut::<Lenia>().max_buffer_size = limit.into();
+    }
+}
+
+/// Continuous cellular automaton with up to three interacting channels,
+/// stored in the colour channels of a double buffered float texture.
+#[derive(Resource, Clone)]
+pub struct Lenia {
+    pub precision: LeniaPrecision,
+    pub convolution: Convolution,
+    /// Kernel weights and spectra, regenerated when the kernels change.
+    kernels: KernelCache,
+    /// Uploaded by `prepare_kernel_buffers` in the render world.
+    buffers: Option<KernelBuffers>,
+    /// Largest storage buffer binding the render device allows, in bytes.
+    max_buffer_size: u64,
+}
+
+impl Default for Lenia {
+    fn default() -> Self {
+        Self {
+            precision: LeniaPrecision::F32,
+            convolution: Convolution::Direct,
+            kernels: KernelCache::default(),
+            buffers: None,
+            max_buffer_size: WgpuLimits::default().max_storage_buffer_binding_size.into(),
+        }
+    }
+}
+
+/// Precision of the state texture. Single channel rules store it in `R32Float`
+/// or `R16Float`, multi-channel rules in `Rgba32Float` or `Rgba16Float`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LeniaPrecision {
+    /// Halves the memory traffic; the single channel `R16Float` needs an
+    /// adapter that supports it as a storage texture.
+    F16,
+    #[default]
+    F32,
+}
+
+/// Parses `f16` or `f32`.
+impl FromStr for LeniaPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "f16" => Ok(Self::F16),
+            "f32" => Ok(Self::F32),
+            _ => Err(format!("expected f16 or f32, got `{s}`")),
+        }
+    }
+}
+
+/// How the potentials, the kernel weighted averages around each cell, are computed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Convolution {
+    /// Sum the (2R+1)^2 taps around every cell.
+    #[default]
+    Direct,
+    /// Multiply the spectrum of the state with the kernel spectrum. The cost
+    /// does not depend on the radius, but both sides of the grid must be powers
+    /// of two up to 1024, the boundary periodic and the FFT buffers within the
+    /// device limits; otherwise this falls back to [`Convolution::Direct`].
+    Fft,
+}
+
+impl Lenia {
+    pub fn with_precision(precision: LeniaPrecision) -> Self {
+        Self {
+            precision,
             ..default()
-        },
-        texture: image.clone(),
-        ..default()
-    });
-    commands.spawn(Camera2dBundle::default());
-    commands.insert_resource(LeniaImage{ texture: image });
+        }
+    }
+
+    /// Format of the state texture holding the channels of `params`.
+    pub fn state_format(&self, params: &LeniaParams) -> TextureFormat {
+        match (self.precision, params.channels() > 1) {
+            (LeniaPrecision::F32, false) => TextureFormat::R32Float,
+            (LeniaPrecision::F32, true) => TextureFormat::Rgba32Float,
+            (LeniaPrecision::F16, false) => TextureFormat::R16Float,
+            (LeniaPrecision::F16, true) => TextureFormat::Rgba16Float,
+        }
+    }
+
+    fn uses_fft(&self, params: &LeniaParams, size: UVec2) -> bool {
+        self.fft_supported(params, size) && self.fft_fits(params, size)
+    }
+
+    /// Whether the FFT passes are asked for and handle the grid.
+    fn fft_supported(&self, params: &LeniaParams, size: UVec2) -> bool {
+        let supported = |side: u32| side.is_power_of_two() && (2..=FFT_MAX_SIZE).contains(&side);
+        self.convolution == Convolution::Fft
+            && params.boundary == Boundary::Periodic
+            && supported(size.x)
+            && supported(size.y)
+    }
+
+    /// Whether the kernel spectra and the FFT scratch memory fit into storage
+    /// buffers of the device, see [`FFT_MAX_SIZE`].
+    fn fft_fits(&self, params: &LeniaParams, size: UVec2) -> bool {
+        let (spectra, scratch) = fft_buffer_sizes(params, size);
+        spectra.max(scratch) <= self.max_buffer_size
+    }
 }
 
+/// Bytes of the kernel spectra and of the FFT scratch memory of `params` on a
+/// grid of `size`.
+fn fft_buffer_sizes(params: &LeniaParams, size: UVec2) -> (u64, u64) {
+    let cell = size.x as u64 * size.y as u64 * std::mem::size_of::<Vec2>() as u64;
+    let kernels = params.wired_kernels().count() as u64;
+    (kernels * cell, (params.channels() as u64 + kernels) * cell)
+}
+
+impl ComputeSimulation for Lenia {
+    type Params = LeniaParams;
+    type Bindings = LeniaBindings;
+
+    const NAME: &'static str = "lenia";
+    const SHADER: &'static str = "shaders/lenia.compute.wgsl";
+    const UNIT_STATE: bool = true;
+
+    fn textures(&self, params: &LeniaParams) -> Vec<TextureFormat> {
+        vec![self.state_format(params)]
+    }
+
+    fn shader_defs(&self, textures: &[TextureFormat]) -> Vec<ShaderDefVal> {
+        storage_format_def(textures[0]).into_iter().map(Into::into).collect()
+    }
+
+    fn boundary(params: &LeniaParams) -> Boundary {
+        params.boundary
+    }
+
+    fn set_boundary(params: &mut LeniaParams, boundary: Boundary) {
+        params.boundary = boundary;
+    }
+
+    fn update_passes(&self, params: &LeniaParams, size: UVec2) -> Vec<UpdatePass> {
+        if self.uses_fft(params, size) {
+            vec![
+                UpdatePass::new("fft_rows").workgroups(Workgroups::Rows).bindings_only(),
+                UpdatePass::new("fft_convolve_columns")
+                    .workgroups(Workgroups::Columns)
+                    .bindings_only(),
+                UpdatePass::new("fft_update").workgroups(Workgroups::Rows),
+            ]
+        } else {
+            vec![UpdatePass::new("update")]
+        }
+    }
+
+    fn bindings(&mut self, params: &LeniaParams, _size: UVec2) -> LeniaBindings {
+        let channel_kernels: Vec<_> = params.wired_kernels().collect();
+        if channel_kernels.len() < params.kernels.len() {
+            warn!("ignoring Lenia kernels wired to channels outside of 0..{MAX_CHANNELS}");
+        }
+        let kernels: Vec<_> = channel_kernels.iter().map(|kernel| kernel.kernel.clone()).collect();
+        let (_, offsets) = self.kernels.weights(&kernels);
+        let kernel_uniforms: Vec<_> = channel_kernels
+            .iter()
+            .zip(offsets)
+            .map(|(kernel, offset)| {
+                let (mu, sigma, intervals) = match kernel.growth {
+                    GrowthFunction::Intervals { birth, survival } => {
+                        (kernel.mu, kernel.sigma, Vec4::new(birth.0, birth.1, survival.0, survival.1))
+                    }
+                    GrowthFunction::SmoothLife { birth, death, alpha_n, alpha_m } => {
+                        (alpha_n, alpha_m, Vec4::new(birth.0, birth.1, death.0, death.1))
+                    }
+                    _ => (kernel.mu, kernel.sigma, Vec4::ZERO),
+                };
+                KernelUniform {
+                    source: kernel.source as u32,
+                    destination: kernel.destination as u32,
+                    weight: kernel.weight,
+                    mu,
+                    sigma,
+                    growth: kernel.growth.shader_id(),
+                    radius: kernel.kernel.radius.max(1),
+                    weights_offset: *offset,
+                    intervals,
+                }
+            })
+            .collect();
 
-#[derive(Resource, Clone, Deref, ExtractResource, AsBindGroup)]
-struct LeniaImage {
-    #[storage_texture(0, image_format = Rgba8Unorm, access = ReadWrite)]
-    texture: Handle<Image>,
+        let buffers = self
+            .buffers
+            .as_ref()
+            .expect("the kernel buffers are prepared before the bind groups");
+        LeniaBindings {
+            params: LeniaUniform {
+                dt: params.dt,
+                integrator: params.integrator as u32,
+                channels: params.channels() as u32,
+                kernel_count: kernel_uniforms.len() as u32,
+            },
+            kernel_spectra: buffers.spectra.clone(),
+            kernel_weights: buffers.weights.clone(),
+            kernels: non_empty(kernel_uniforms),
+            fft_scratch: buffers.fft_scratch.clone(),
+        }
+    }
 }
 
-#[derive(Resource)]
-struct LeniaImageBindGroup(BindGroup);
+/// Pressing I switches the running Lenia rule to the next [`Integrator`].
+fn cycle_integrator(
+    keys: Res<ButtonInput<KeyCode>>,
+    active: Res<SimulationActive<Lenia>>,
+    mut params: ResMut<LeniaParams>,
+) {
+    if keys.just_pressed(KeyCode::KeyI) && active.active {
+        params.integrator = params.integrator.next();
+        info!("integrator: {:?}", params.integrator);
+    }
+}
+
+/// The kernel data that only changes with the kernels or the grid size, on
+/// the GPU.
+#[derive(Clone)]
+struct KernelBuffers {
+    kernels: Vec<LeniaKernel>,
+    channels: usize,
+    /// The grid size when the FFT passes run, which the spectra and the
+    /// scratch memory are made for; both are left empty otherwise.
+    fft_size: Option<UVec2>,
+    weights: Buffer,
+    spectra: Buffer,
+    fft_scratch: Buffer,
+}
 
-fn prepare_bind_group(
-    mut commands: Commands,
-    pipeline: Res<LeniaPipeline>,
-    gpu_images: Res<RenderAssets<Image>>,
-    lenia_image: Res<LeniaImage>,
+/// Uploads the kernel weights and spectra and allocates the FFT scratch
+/// memory when the kernels or the grid size change, so that other parameter
+/// changes only write the uniforms.
+fn prepare_kernel_buffers(
+    mut lenia: ResMut<Lenia>,
+    params: Res<LeniaParams>,
+    images: Res<SimulationImages<Lenia>>,
     render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
 ) {
-    let view = gpu_images.get(&lenia_image.texture).unwrap();
-    let bind_group = render_device.create_bind_group(
-        None,
-        &pipeline.texture_bind_group_layout,
-        &BindGroupEntries::single(&view.texture_view),
-    );
-    commands.insert_resource(LeniaImageBindGroup(bind_group));
-}
-
-#[derive(Resource)]
-pub struct LeniaPipeline {
-    texture_bind_group_layout: BindGroupLayout,
-    init_pipeline: CachedComputePipelineId,
-    update_pipeline: CachedComputePipelineId,
-    // render_pipeline: CachedRenderPipelineId,
-}
-
-// パイプラインの初期化を定義
-impl FromWorld for LeniaPipeline {
-fn from_world(world: &mut World) -> Self {
-        let render_device = world.resource::<RenderDevice>();
-        let texture_bind_group_layout = LeniaImage::bind_group_layout(render_device);
-        let shader = world
-            .resource::<AssetServer>()
-            .load("shaders/lenia.compute.wgsl");
-        let pipeline_cache = world.resource::<PipelineCache>();
-        let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from("init"),
-        });
-        let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader,
-            shader_defs: vec![],
-            entry_point: Cow::from("update"),
-        });
-
-        // 同じ名前の型なので省略
-        LeniaPipeline {
-            texture_bind_group_layout,
-            init_pipeline,
-            update_pipeline,
-            // render_pipeline,
+    let kernels: Vec<_> = params.wired_kernels().map(|kernel| kernel.kernel.clone()).collect();
+    let channels = params.channels();
+    let fft_size = lenia.uses_fft(&params, images.size).then_some(images.size);
+    let current = lenia.buffers.as_ref().is_some_and(|buffers| {
+        buffers.kernels == kernels && buffers.channels == channels && buffers.fft_size == fft_size
+    });
+    if current {
+        return;
+    }
+    if fft_size.is_none() && lenia.fft_supported(&params, images.size) {
+        let (spectra, scratch) = fft_buffer_sizes(&params, images.size);
+        warn!(
+            "the Lenia FFT buffers take {} bytes on a {}x{} grid, more than the {} the device allows; \
+             falling back to direct convolution",
+            spectra.max(scratch),
+            images.size.x,
+            images.size.y,
+            lenia.max_buffer_size,
+        );
+    }
+
+    let weights = lenia.kernels.weights(&kernels).0.to_vec();
+    let weights = upload(non_empty(weights), &render_device, &render_queue);
+    let (spectra, scratch_len) = match fft_size {
+        Some(size) => (
+            lenia.kernels.spectra(&kernels, size).to_vec(),
+            (channels + kernels.len()) * (size.x * size.y) as usize,
+        ),
+        None => (Vec::new(), 1),
+    };
+    let fft_scratch = render_device.create_buffer(&BufferDescriptor {
+        label: Some("lenia_fft_scratch"),
+        size: (scratch_len * std::mem::size_of::<Vec2>()) as u64,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    lenia.buffers = Some(KernelBuffers {
+        kernels,
+        channels,
+        fft_size,
+        weights,
+        spectra: upload(non_empty(spectra), &render_device, &render_queue),
+        fft_scratch,
+    });
+}
+
+fn upload<T: ShaderType + WriteInto>(values: T, render_device: &RenderDevice, render_queue: &RenderQueue) -> Buffer {
+    let mut buffer = StorageBuffer::from(values);
+    buffer.write_buffer(render_device, render_queue);
+    buffer.buffer().unwrap().clone()
+}
+
+/// Pads `values` to a single default element, as storage bindings can not be empty.
+fn non_empty<T: Default>(mut values: Vec<T>) -> Vec<T> {
+    if values.is_empty() {
+        values.push(T::default());
+    }
+    values
+}
+
+/// Number of channels a Lenia rule can wire kernels between.
+pub const MAX_CHANNELS: usize = 3;
+
+/// Parameters of the Lenia update rule. Modify this resource at runtime to
+/// change the dynamics; the kernels are only regenerated when one of their
+/// [`LeniaKernel`]s changes.
+#[derive(Resource, Clone, Debug, ExtractResource)]
+pub struct LeniaParams {
+    /// time step, the reciprocal of the time resolution T
+    pub dt: f32,
+    pub integrator: Integrator,
+    pub boundary: Boundary,
+    /// Wiring of the channels. Each step, every channel that is the
+    /// destination of a kernel is advanced by the weighted sum of the growth
+    /// of the kernels writing into it; other channels are left unchanged.
+    pub kernels: Vec<ChannelKernel>,
+}
+
+impl Default for LeniaParams {
+    fn default() -> Self {
+        Self {
+            dt: 0.1,
+            integrator: Integrator::Euler,
+            boundary: Boundary::Periodic,
+            kernels: vec![ChannelKernel {
+                growth: GrowthFunction::StateModulated,
+                ..default()
+            }],
         }
     }
 }
 
-enum LeniaState {
-    Loading,
-    Init,
-    Update,
+impl LeniaParams {
+    /// The kernels wired to channels the shader has, the others are ignored.
+    fn wired_kernels(&self) -> impl Iterator<Item = &ChannelKernel> {
+        self.kernels
+            .iter()
+            .filter(|kernel| kernel.source < MAX_CHANNELS && kernel.destination < MAX_CHANNELS)
+    }
+
+    /// Number of channels the kernels read from or write to.
+    pub fn channels(&self) -> usize {
+        self.kernels
+            .iter()
+            .map(|kernel| kernel.source.max(kernel.destination) + 1)
+            .max()
+            .unwrap_or(1)
+            .min(MAX_CHANNELS)
+    }
 }
 
-struct LeniaNode {
-    state: LeniaState,
+/// A kernel reading the `source` channel, whose growth is added to the
+/// `destination` channel scaled by `weight`. Channels are numbered from 0 and
+/// map to the red, green and blue components of the state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelKernel {
+    pub source: usize,
+    pub destination: usize,
+    pub weight: f32,
+    /// growth center
+    pub mu: f32,
+    /// growth width
+    pub sigma: f32,
+    pub growth: GrowthFunction,
+    pub kernel: LeniaKernel,
 }
 
-impl Default for LeniaNode {
+impl Default for ChannelKernel {
     fn default() -> Self {
         Self {
-            state: LeniaState::Loading,
+            source: 0,
+            destination: 0,
+            weight: 1.0,
+            mu: 0.14,
+            sigma: 0.014,
+            growth: GrowthFunction::Gaussian,
+            kernel: LeniaKernel::default(),
         }
     }
 }
 
-impl render_graph::Node for LeniaNode {
-    fn update(&mut self, world: &mut World) {
-        let pipeline = world.resource::<LeniaPipeline>();
-        let pipeline_cache = world.resource::<PipelineCache>();
+/// Mapping from the potential `u` to a growth rate in `[-1, 1]`, peaking at
+/// `mu`. The last three ignore `mu` and `sigma` and build the rule families
+/// of [`families::LargerThanLife`] and [`families::SmoothLife`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GrowthFunction {
+    /// `2 exp(-(u - mu)^2 / 2 sigma^2) - 1`, as in the original Lenia.
+    #[default]
+    Gaussian,
+    /// `2 max(0, 1 - (u - mu)^2 / 9 sigma^2)^4 - 1`
+    Polynomial,
+    /// 1 within `sigma` of `mu`, -1 elsewhere.
+    Step,
+    /// Gaussian of the potential scaled by `1 + 0.2 (a - 0.5)`, where `a` is
+    /// the current state of the cell, so denser cells see a slightly stronger
+    /// potential. The rule this project started with.
+    StateModulated,
+    /// 1 when `u` lies within `survival` for a live cell (`a > 0.5`) or within
+    /// `birth` for a dead one, -1 elsewhere. Both intervals are inclusive.
+    Intervals { birth: (f32, f32), survival: (f32, f32) },
+    /// No growth of its own: the potential is the inner filling `m` the
+    /// following [`GrowthFunction::SmoothLife`] kernels see.
+    Filling,
+    /// Rafler's SmoothLife transition `2 s(u, m) - 1` of the outer filling `u`,
+    /// where `s` is 1 within the interval blended from `birth` to `death` by
+    /// `sigmoid(m, 0.5, alpha_m)`, with edges of width `alpha_n`.
+    SmoothLife {
+        birth: (f32, f32),
+        death: (f32, f32),
+        alpha_n: f32,
+        alpha_m: f32,
+    },
+}
 
-        // if the corresponding pipeline has loaded, transition to the next stage
-        match self.state {
-            LeniaState::Loading => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.init_pipeline)
-                {
-                    self.state = LeniaState::Init;
-                }
-            }
-            LeniaState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
-                {
-                    self.state = LeniaState::Update;
-                }
-            }
-            LeniaState::Update => {}
+impl GrowthFunction {
+    /// The `GROWTH_*` constant of `lenia.compute.wgsl`.
+    fn shader_id(self) -> u32 {
+        match self {
+            GrowthFunction::Gaussian => 0,
+            GrowthFunction::Polynomial => 1,
+            GrowthFunction::Step => 2,
+            GrowthFunction::StateModulated => 3,
+            GrowthFunction::Intervals { .. } => 4,
+            GrowthFunction::Filling => 5,
+            GrowthFunction::SmoothLife { .. } => 6,
         }
     }
+}
+
+/// How the state `a` is advanced by one step given the growth rate `g`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    /// `clamp(a + dt g, 0, 1)`, explicit Euler with hard clipping.
+    #[default]
+    Euler = 0,
+    /// `soft_clip(a + dt g)`, Euler with the corners of the clip into `[0, 1]`
+    /// rounded off over [`SOFT_CLIP_WIDTH`]; the identity in between.
+    SoftClip = 1,
+    /// `a + dt ((g + 1) / 2 - a)`, Asymptotic Lenia: relaxes towards the target
+    /// state the growth maps to, which stays in `[0, 1]` without clipping.
+    Asymptotic = 2,
+}
+
+impl Integrator {
+    const ALL: [Integrator; 3] = [Integrator::Euler, Integrator::SoftClip, Integrator::Asymptotic];
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|integrator| *integrator == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Width of the rounded corners of [`Integrator::SoftClip`], as in `lenia.compute.wgsl`.
+pub const SOFT_CLIP_WIDTH: f32 = 0.05;
 
-    fn run(
-        &self,
-        _graph: &mut render_graph::RenderGraphContext,
-        render_context: &mut RenderContext,
-        world: &World,
-    ) -> Result<(), render_graph::NodeRunError> {
-        let texture_bind_group = &world.resource::<LeniaImageBindGroup>().0;
-        let pipeline_cache = world.resource::<PipelineCache>();
-        let pipeline = world.resource::<LeniaPipeline>();
-
-        let mut pass = render_context
-            .command_encoder()
-            .begin_compute_pass(&ComputePassDescriptor::default());
-
-        pass.set_bind_group(0, texture_bind_group, &[]);
-
-        // select the pipeline based on the current state
-        match self.state {
-            LeniaState::Loading => {}
-            LeniaState::Init => {
-                let init_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.init_pipeline)
-                    .unwrap();
-                pass.set_pipeline(init_pipeline);
-                pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
-            }
-            LeniaState::Update => {
-                let update_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.update_pipeline)
-                    .unwrap();
-                pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
-            }
+/// Radially symmetric convolution kernel made of concentric rings, as in the
+/// original Lenia. At a distance `r` from the centre, in units of `radius`,
+/// the `B` rings split `[0, 1)` into equal bands; band `i` has height
+/// `rings[i]` and shape `core(B * r mod 1)`. Taps at `r >= 1` are zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeniaKernel {
+    /// kernel radius in cells
+    pub radius: i32,
+    /// Heights of the rings from the centre outwards, e.g. `[1.0, 2.0 / 3.0, 1.0 / 3.0]`.
+    pub rings: Vec<f32>,
+    pub core: KernelCore,
+}
+
+impl Default for LeniaKernel {
+    fn default() -> Self {
+        Self {
+            radius: 15,
+            rings: vec![1.0],
+            core: KernelCore::Gaussian {
+                rho: 0.5,
+                omega: 0.15,
+            },
         }
+    }
+}
+
+/// Shape of a single kernel ring over its band `r` in `[0, 1)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KernelCore {
+    /// `exp(4 - 1 / (r (1 - r)))`
+    Exponential,
+    /// `(4 r (1 - r))^alpha`, the original Lenia uses `alpha = 4`.
+    Polynomial { alpha: f32 },
+    /// 1 on `[1/4, 3/4]`, 0 elsewhere.
+    Rectangular,
+    /// 1 over the whole band, for neighbourhoods that count cells.
+    Flat,
+    /// Bell curve centred at `rho` with width `omega`.
+    Gaussian { rho: f32, omega: f32 },
+}
 
-        Ok(())
+mod uniforms {
+    // see `uniforms` in simulation.rs
+    #![allow(dead_code)]
+
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    /// The part of [`LeniaParams`](super::LeniaParams) shared by all kernels,
+    /// as read by `lenia.compute.wgsl`.
+    #[derive(Clone, Copy, Debug, ShaderType)]
+    pub struct LeniaUniform {
+        pub dt: f32,
+        pub integrator: u32,
+        pub channels: u32,
+        pub kernel_count: u32,
+    }
+
+    /// A [`ChannelKernel`](super::ChannelKernel) as read by `lenia.compute.wgsl`.
+    #[derive(Clone, Copy, Debug, Default, ShaderType)]
+    pub struct KernelUniform {
+        pub source: u32,
+        pub destination: u32,
+        pub weight: f32,
+        pub mu: f32,
+        pub sigma: f32,
+        pub growth: u32,
+        pub radius: i32,
+        pub weights_offset: u32,
+        pub intervals: Vec4,
     }
 }
+
+#[derive(AsBindGroup)]
+pub struct LeniaBindings {
+    #[uniform(0)]
+    params: LeniaUniform,
+    /// Row major spectra of all kernels back to back, see [`LeniaKernel::spectrum`].
+    #[storage(1, read_only, visibility(compute), buffer)]
+    kernel_spectra: Buffer,
+    /// Row major (2R+1)^2 weights of all kernels back to back, see [`LeniaKernel::weights`].
+    #[storage(2, read_only, visibility(compute), buffer)]
+    kernel_weights: Buffer,
+    #[storage(3, read_only, visibility(compute))]
+    kernels: Vec<KernelUniform>,
+    /// Working memory of the FFT passes: the spectrum of every channel
+    /// followed by the convolved spectrum of every kernel.
+    #[storage(4, visibility(compute), buffer)]
+    fft_scratch: Buffer,
+}
@@ -0,0 +1,505 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+use super::{ChannelKernel, GrowthFunction, KernelCore, Lenia, LeniaKernel, LeniaParams};
+use crate::pattern::{InitialPattern, PatternAsset, PatternError, PatternPlacement};
+use crate::simulation::{SimulationActive, SnapshotTaken, StateSnapshots};
+
+/// A creature in the JSON format of the Lenia reference implementation, e.g.
+///
+/// ```json
+/// {"code": "O2u", "name": "Orbium unicaudatus",
+///  "params": {"R": 13, "T": 10, "b": "1", "m": 0.15, "s": 0.015, "kn": 1, "gn": 1},
+///  "cells": "7.MD6.qL$6.pKqEqFURpApBRAqQ$..."}
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Animal {
+    #[serde(default)]
+    pub code: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cname: String,
+    pub params: AnimalParams,
+    /// Cell string, see [`PatternAsset::from_rle`].
+    pub cells: String,
+    /// Name of the family header preceding the record in a list of animals.
+    #[serde(skip)]
+    pub family: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnimalParams {
+    /// kernel radius in cells
+    #[serde(rename = "R")]
+    pub radius: i32,
+    /// time resolution, the reciprocal of the time step
+    #[serde(rename = "T")]
+    pub time_resolution: f32,
+    /// ring heights, written as e.g. `"1,2/3,1/3"`
+    #[serde(rename = "b", serialize_with = "write_rings", deserialize_with = "read_rings")]
+    pub rings: Vec<f32>,
+    /// growth center
+    #[serde(rename = "m")]
+    pub mu: f32,
+    /// growth width
+    #[serde(rename = "s")]
+    pub sigma: f32,
+    /// kernel core: 1 polynomial, 2 exponential, 3 step
+    pub kn: u32,
+    /// growth function: 1 polynomial, 2 Gaussian, 3 step
+    pub gn: u32,
+}
+
+impl AnimalParams {
+    /// The rule as single channel [`LeniaParams`].
+    pub fn lenia_params(&self) -> Result<LeniaParams, AnimalError> {
+        let core = match self.kn {
+            1 => KernelCore::Polynomial { alpha: 4.0 },
+            2 => KernelCore::Exponential,
+            3 => KernelCore::Rectangular,
+            kn => return Err(AnimalError::UnsupportedKernel(kn)),
+        };
+        let growth = match self.gn {
+            1 => GrowthFunction::Polynomial,
+            2 => GrowthFunction::Gaussian,
+            3 => GrowthFunction::Step,
+            gn => return Err(AnimalError::UnsupportedGrowth(gn)),
+        };
+        Ok(LeniaParams {
+            dt: 1.0 / self.time_resolution,
+            kernels: vec![ChannelKernel {
+                mu: self.mu,
+                sigma: self.sigma,
+                growth,
+                kernel: LeniaKernel {
+                    radius: self.radius,
+                    rings: self.rings.clone(),
+                    core,
+                },
+                ..default()
+            }],
+            ..default()
+        })
+    }
+}
+
+impl Animal {
+    /// The rule of the animal as single channel [`LeniaParams`].
+    pub fn params(&self) -> Result<LeniaParams, AnimalError> {
+        self.params.lenia_params()
+    }
+
+    pub fn pattern(&self) -> Result<PatternAsset, PatternError> {
+        PatternAsset::from_rle(&self.cells)
+    }
+
+    /// Record of a single channel rule and the first channel of `cells`.
+    pub fn new(name: &str, params: &LeniaParams, cells: &PatternAsset) -> Result<Self, AnimalError> {
+        let [kernel] = params.kernels.as_slice() else {
+            return Err(AnimalError::NotExportable("the rule has more than one kernel"));
+        };
+        if kernel.source != 0 || kernel.destination != 0 || kernel.weight != 1.0 {
+            return Err(AnimalError::NotExportable("the kernel is wired between channels"));
+        }
+        let kn = match kernel.kernel.core {
+            KernelCore::Polynomial { alpha: 4.0 } => 1,
+            KernelCore::Exponential => 2,
+            KernelCore::Rectangular => 3,
+            _ => return Err(AnimalError::NotExportable("the kernel core has no reference equivalent")),
+        };
+        let gn = match kernel.growth {
+            GrowthFunction::Polynomial => 1,
+            GrowthFunction::Gaussian => 2,
+            GrowthFunction::Step => 3,
+            _ => return Err(AnimalError::NotExportable("the growth function has no reference equivalent")),
+        };
+        Ok(Self {
+            code: String::new(),
+            name: name.to_string(),
+            cname: String::new(),
+            params: AnimalParams {
+                radius: kernel.kernel.radius,
+                time_resolution: 1.0 / params.dt,
+                rings: kernel.kernel.rings.clone(),
+                mu: kernel.mu,
+                sigma: kernel.sigma,
+                kn,
+                gn,
+            },
+            cells: encode_rle(cells.channels.first().map_or(&[][..], Vec::as_slice), cells.size.x as usize),
+            family: String::new(),
+        })
+    }
+}
+
+/// Encodes cell values row by row into the cell string format read by
+/// [`PatternAsset::from_rle`].
+pub fn encode_rle(values: &[f32], width: usize) -> String {
+    let mut rows: Vec<Vec<u8>> = values
+        .chunks(width.max(1))
+        .map(|row| row.iter().map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8).collect())
+        .collect();
+    for row in &mut rows {
+        while row.last() == Some(&0) {
+            row.pop();
+        }
+    }
+    while rows.last().is_some_and(Vec::is_empty) {
+        rows.pop();
+    }
+
+    let symbol = |value: u8| match value {
+        0 => ".".to_string(),
+        1..=24 => char::from(b'A' + value - 1).to_string(),
+        _ => {
+            let value = value - 25;
+            format!("{}{}", char::from(b'p' + value / 24), char::from(b'A' + value % 24))
+        }
+    };
+    let run = |out: &mut String, count: usize, token: &str| {
+        if count > 1 {
+            out.push_str(&count.to_string());
+        }
+        out.push_str(token);
+    };
+
+    let mut out = String::new();
+    let mut row_breaks = 0;
+    for (y, row) in rows.into_iter().enumerate() {
+        if y > 0 {
+            row_breaks += 1;
+        }
+        if row.is_empty() {
+            continue;
+        }
+        if row_breaks > 0 {
+            run(&mut out, row_breaks, "$");
+            row_breaks = 0;
+        }
+        let mut cells = row.into_iter().peekable();
+        while let Some(value) = cells.next() {
+            let mut count = 1;
+            while cells.peek() == Some(&value) {
+                cells.next();
+                count += 1;
+            }
+            run(&mut out, count, &symbol(value));
+        }
+    }
+    out.push('!');
+    out
+}
+
+fn read_rings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+    let fraction = |text: &str| -> Option<f32> {
+        match text.trim().split_once('/') {
+            Some((numerator, denominator)) => {
+                Some(numerator.trim().parse::<f32>().ok()? / denominator.trim().parse::<f32>().ok()?)
+            }
+            None => text.trim().parse().ok(),
+        }
+    };
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(text) => text
+            .split(',')
+            .map(|ring| fraction(ring).ok_or_else(|| serde::de::Error::custom(format!("invalid ring `{ring}`"))))
+            .collect(),
+        serde_json::Value::Number(number) => Ok(vec![number.as_f64().unwrap_or_default() as f32]),
+        value => Vec::<f32>::deserialize(value).map_err(serde::de::Error::custom),
+    }
+}
+
+fn write_rings<S: Serializer>(rings: &[f32], serializer: S) -> Result<S::Ok, S::Error> {
+    // small fractions as the reference implementation writes them
+    let ring = |value: f32| {
+        (1..=12)
+            .find(|denominator| {
+                let numerator = value * *denominator as f32;
+                (numerator - numerator.round()).abs() < 1e-4
+            })
+            .map_or_else(
+                || value.to_string(),
+                |denominator| match (value * denominator as f32).round() as i32 {
+                    numerator if denominator == 1 => numerator.to_string(),
+                    numerator => format!("{numerator}/{denominator}"),
+                },
+            )
+    };
+    serializer.serialize_str(&rings.iter().map(|value| ring(*value)).collect::<Vec<_>>().join(","))
+}
+
+#[derive(Debug)]
+pub enum AnimalError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedKernel(u32),
+    UnsupportedGrowth(u32),
+    NotExportable(&'static str),
+}
+
+impl fmt::Display for AnimalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnimalError::Io(err) => write!(f, "could not read animals: {err}"),
+            AnimalError::Json(err) => write!(f, "invalid animal record: {err}"),
+            AnimalError::UnsupportedKernel(kn) => write!(f, "unsupported kernel core kn={kn}"),
+            AnimalError::UnsupportedGrowth(gn) => write!(f, "unsupported growth function gn={gn}"),
+            AnimalError::NotExportable(reason) => write!(f, "can not export: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for AnimalError {}
+
+impl From<std::io::Error> for AnimalError {
+    fn from(err: std::io::Error) -> Self {
+        AnimalError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for AnimalError {
+    fn from(err: serde_json::Error) -> Self {
+        AnimalError::Json(err)
+    }
+}
+
+/// Animals from a JSON file holding either a single record or a list of
+/// them, such as `animals.json` of the reference implementation. In a list,
+/// records without cells are family headers naming the animals that follow.
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct AnimalLibrary {
+    pub animals: Vec<Animal>,
+}
+
+impl AnimalLibrary {
+    pub fn from_json(bytes: &[u8]) -> Result<Self, AnimalError> {
+        let records = match serde_json::from_slice(bytes)? {
+            serde_json::Value::Array(records) => records,
+            record => vec![record],
+        };
+        let mut family = String::new();
+        let mut animals = Vec::new();
+        for record in records {
+            if record.get("cells").is_none() {
+                if let Some(name) = record.get("name").and_then(|name| name.as_str()) {
+                    family = name.to_string();
+                }
+                continue;
+            }
+            let mut animal: Animal = serde_json::from_value(record)?;
+            animal.family = family.clone();
+            animals.push(animal);
+        }
+        Ok(Self { animals })
+    }
+}
+
+#[derive(Default)]
+pub struct AnimalLoader;
+
+impl AssetLoader for AnimalLoader {
+    type Asset = AnimalLibrary;
+    type Settings = ();
+    type Error = AnimalError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<AnimalLibrary, AnimalError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            AnimalLibrary::from_json(&bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json"]
+    }
+}
+
+/// Animal Lenia runs: its rule replaces the [`LeniaParams`] and its cells
+/// seed the grid once the library is loaded, and again whenever this changes.
+#[derive(Resource, Clone, Debug)]
+pub struct ActiveAnimal {
+    pub library: Handle<AnimalLibrary>,
+    /// Index into [`AnimalLibrary::animals`].
+    pub index: usize,
+    pub placement: PatternPlacement,
+}
+
+pub(super) fn apply_animal(
+    mut commands: Commands,
+    active: Option<Res<ActiveAnimal>>,
+    libraries: Res<Assets<AnimalLibrary>>,
+    mut events: EventReader<AssetEvent<AnimalLibrary>>,
+    mut patterns: ResMut<Assets<PatternAsset>>,
+    mut params: ResMut<LeniaParams>,
+) {
+    let Some(active) = active else {
+        return;
+    };
+    let loaded = events
+        .read()
+        .filter(|event| event.is_loaded_with_dependencies(&active.library))
+        .count()
+        > 0;
+    if !active.is_changed() && !loaded {
+        return;
+    }
+    let Some(animal) = libraries.get(&active.library).and_then(|library| library.animals.get(active.index)) else {
+        return;
+    };
+    match animal.params().map_err(|err| err.to_string()).and_then(|animal_params| {
+        Ok((animal_params, animal.pattern().map_err(|err| err.to_string())?))
+    }) {
+        Ok((animal_params, pattern)) => {
+            *params = animal_params;
+            commands.insert_resource(InitialPattern::<Lenia>::new(patterns.add(pattern), active.placement));
+        }
+        Err(err) => warn!("can not load {}: {err}", animal.name),
+    }
+}
+
+/// Pressing E writes the current rule and the smallest region holding all
+/// live cells to `exports/lenia-<unix time>.json`.
+pub(super) fn export_animal(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut snapshots: ResMut<StateSnapshots<Lenia>>,
+    mut taken: EventReader<SnapshotTaken<Lenia>>,
+    mut pending: Local<bool>,
+    params: Res<LeniaParams>,
+    active: Res<SimulationActive<Lenia>>,
+) {
+    if keys.just_pressed(KeyCode::KeyE) && active.active {
+        snapshots.request();
+        *pending = true;
+    }
+    for SnapshotTaken { snapshot, .. } in taken.read() {
+        if !std::mem::take(&mut *pending) {
+            continue;
+        }
+        let live = |x: u32, y: u32| snapshot.cell(x, y).x > 0.5 / 255.0;
+        let cells = (0..snapshot.size.y).flat_map(|y| (0..snapshot.size.x).map(move |x| (x, y)));
+        let Some(bounds) = cells.filter(|(x, y)| live(*x, *y)).fold(None, |bounds: Option<URect>, (x, y)| {
+            let cell = URect::new(x, y, x + 1, y + 1);
+            Some(bounds.map_or(cell, |bounds| bounds.union(cell)))
+        }) else {
+            warn!("nothing to export, the grid is empty");
+            continue;
+        };
+        let size = bounds.size();
+        let region = PatternAsset {
+            size,
+            channels: vec![(bounds.min.y..bounds.max.y)
+                .flat_map(|y| (bounds.min.x..bounds.max.x).map(move |x| snapshot.cell(x, y).x))
+                .collect()],
+        };
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let name = format!("lenia-{time}");
+        let path = format!("exports/{name}.json");
+        let written = Animal::new(&name, &params, &region).and_then(|animal| {
+            std::fs::create_dir_all("exports")?;
+            std::fs::write(&path, serde_json::to_string_pretty(&animal)?)?;
+            Ok(())
+        });
+        match written {
+            Ok(()) => info!("exported {}x{} cells to {path}", size.x, size.y),
+            Err(err) => warn!("{err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_strings_round_trip() {
+        let width = 5;
+        #[rustfmt::skip]
+        let values = [
+            0.0, 0.0, 1.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 0.0, 0.0,
+            0.1, 0.1, 0.1, 0.5, 0.0,
+            0.0, 0.0, 0.0, 0.0, 0.0,
+        ];
+        let cells = encode_rle(&values, width);
+        assert_eq!(cells, "2.2yO3$3pBtH!");
+
+        let pattern = PatternAsset::from_rle(&cells).unwrap();
+        assert_eq!(pattern.size, UVec2::new(4, 4));
+        let decoded = &pattern.channels[0];
+        for (y, row) in values.chunks(width).take(4).enumerate() {
+            for (x, value) in row.iter().take(4).enumerate() {
+                assert_eq!(decoded[y * 4 + x], (value * 255.0).round() / 255.0, "cell ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn reads_a_list_with_family_headers() {
+        let json = br#"[
+            {"code": ">1", "name": "Single ring"},
+            {"code": "O", "name": "Orbium", "params": {"R": 13, "T": 10, "b": "1", "m": 0.15, "s": 0.015, "kn": 1, "gn": 1}, "cells": "2A!"},
+            {"name": "Two rings", "params": {"R": 10, "T": 5, "b": "1,2/3", "m": 0.2, "s": 0.02, "kn": 2, "gn": 2}, "cells": "yO!"}
+        ]"#;
+        let library = AnimalLibrary::from_json(json).unwrap();
+        let [orbium, rings] = library.animals.as_slice() else {
+            panic!("expected two animals, got {:?}", library.animals);
+        };
+        assert_eq!((orbium.code.as_str(), orbium.family.as_str()), ("O", "Single ring"));
+        assert_eq!(orbium.params.rings, vec![1.0]);
+        assert_eq!(rings.code, "");
+        assert_eq!(rings.params.rings, vec![1.0, 2.0 / 3.0]);
+
+        let params = rings.params().unwrap();
+        assert_eq!(params.dt, 0.2);
+        assert_eq!(params.kernels[0].kernel.core, KernelCore::Exponential);
+        assert_eq!(params.kernels[0].growth, GrowthFunction::Gaussian);
+    }
+
+    #[test]
+    fn reads_a_single_record() {
+        let json = br#"{"name": "Blob", "params": {"R": 8, "T": 4, "b": [1, 0.5], "m": 0.3, "s": 0.03, "kn": 3, "gn": 3}, "cells": "A!"}"#;
+        let library = AnimalLibrary::from_json(json).unwrap();
+        assert_eq!(library.animals.len(), 1);
+        assert_eq!(library.animals[0].params.rings, vec![1.0, 0.5]);
+        assert!(matches!(
+            AnimalLibrary::from_json(br#"{"name": "Blob", "cells": "A!"}"#),
+            Err(AnimalError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let json = br#"{"code": "O", "name": "Orbium", "params": {"R": 13, "T": 10, "b": "1,2/3,1/12", "m": 0.15, "s": 0.015, "kn": 1, "gn": 1}, "cells": "2.2yO$pA!"}"#;
+        let animal = &AnimalLibrary::from_json(json).unwrap().animals[0];
+        let written = serde_json::to_string(animal).unwrap();
+        assert!(written.contains(r#""b":"1,2/3,1/12""#), "{written}");
+
+        let exported = Animal::new(&animal.name, &animal.params().unwrap(), &animal.pattern().unwrap()).unwrap();
+        assert_eq!(exported.cells, animal.cells);
+        assert_eq!(serde_json::to_value(&exported.params).unwrap(), serde_json::to_value(&animal.params).unwrap());
+    }
+
+    #[test]
+    fn bundled_creatures_load() {
+        let library = AnimalLibrary::from_json(include_bytes!("../../assets/patterns/creatures.json")).unwrap();
+        assert!(!library.animals.is_empty());
+        for animal in &library.animals {
+            animal.params().unwrap_or_else(|err| panic!("{}: {err}", animal.name));
+            animal.pattern().unwrap_or_else(|err| panic!("{}: {err}", animal.name));
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+
+use super::{ChannelKernel, GrowthFunction, Integrator, LeniaParams, MAX_CHANNELS, SOFT_CLIP_WIDTH};
+use crate::pattern::{PatternAsset, PatternPlacement};
+use crate::simulation::{Boundary, StateSnapshot};
+
+/// Lenia on the CPU: the kernels, growth functions and integrators of
+/// `lenia.compute.wgsl` applied to plain `Vec<f32>` grids, one per channel.
+///
+/// It convolves directly, so it is only practical for small grids and radii,
+/// but needs no GPU. It serves as the reference the shader is checked
+/// against and runs batches on machines without a graphics adapter.
+#[derive(Clone)]
+pub struct CpuLenia {
+    pub params: LeniaParams,
+    pub size: UVec2,
+    /// Row major state of every channel, [`LeniaParams::channels`] of them.
+    pub channels: Vec<Vec<f32>>,
+    /// Row major walls, which hold no state; empty when there are none.
+    pub solid: Vec<bool>,
+}
+
+/// A [`ChannelKernel`] with its nonzero taps, as `(x, y, weight)` offsets.
+struct KernelTaps<'a> {
+    kernel: &'a ChannelKernel,
+    taps: Vec<(i32, i32, f32)>,
+}
+
+impl CpuLenia {
+    /// An empty grid of `size` cells.
+    pub fn new(params: LeniaParams, size: UVec2) -> Self {
+        let cells = (size.x * size.y) as usize;
+        Self {
+            channels: vec![vec![0.0; cells]; params.channels()],
+            params,
+            size,
+            solid: Vec::new(),
+        }
+    }
+
+    /// Fills the top left corner with noise, like the `init` entry point. GPUs
+    /// evaluate the `sin` of its hash less precisely, so the noise is similar
+    /// rather than the same.
+    pub fn seed_random(&mut self) {
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            channel.fill(0.0);
+            for y in 0..self.size.y.min(300) {
+                for x in 0..self.size.x.min(100) {
+                    let p = Vec2::new(x as f32, y as f32) + c as f32 * Vec2::new(17.0, 31.0);
+                    channel[(y * self.size.x + x) as usize] = hash(p).clamp(0.0, 1.0);
+                }
+            }
+        }
+        self.clear_walls();
+    }
+
+    /// Replaces the state by `pattern`, like the `stamp` entry point of
+    /// `pattern.compute.wgsl`: cells outside of the pattern are cleared.
+    pub fn stamp(&mut self, pattern: &PatternAsset, placement: PatternPlacement) {
+        let center = self.size.as_vec2() / 2.0 + placement.position;
+        let (sin, cos) = placement.rotation.sin_cos();
+        let pattern_size = pattern.size.as_ivec2();
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                let offset = Vec2::new(x as f32, y as f32) + 0.5 - center;
+                let unrotated = Vec2::new(cos * offset.x - sin * offset.y, sin * offset.x + cos * offset.y);
+                let texel = (unrotated / placement.scale + pattern.size.as_vec2() * 0.5).floor().as_ivec2();
+                let inside = texel.cmpge(IVec2::ZERO).all() && texel.cmplt(pattern_size).all();
+                let cell = (y * self.size.x + x) as usize;
+                for (c, channel) in self.channels.iter_mut().enumerate() {
+                    channel[cell] = match pattern.channels.get(c) {
+                        Some(values) if inside => values[(texel.y * pattern_size.x + texel.x) as usize],
+                        _ => 0.0,
+                    };
+                }
+            }
+        }
+        self.clear_walls();
+    }
+
+    /// Advances the state by one step, like the `update` entry point.
+    pub fn step(&mut self) {
+        let kernels: Vec<_> = self
+            .params
+            .kernels
+            .iter()
+            .filter(|kernel| kernel.source < self.channels.len() && kernel.destination < self.channels.len())
+            .map(|kernel| {
+                let radius = kernel.kernel.radius.max(1);
+                let offsets = (-radius..=radius).flat_map(|j| (-radius..=radius).map(move |i| (i, j)));
+                let taps = offsets
+                    .zip(kernel.kernel.weights())
+                    .filter(|(_, weight)| *weight != 0.0)
+                    .map(|((i, j), weight)| (i, j, weight))
+                    .collect();
+                KernelTaps { kernel, taps }
+            })
+            .collect();
+
+        let mut next = self.channels.clone();
+        for y in 0..self.size.y as i32 {
+            for x in 0..self.size.x as i32 {
+                let cell = (y as u32 * self.size.x + x as u32) as usize;
+                let mut state = [0.0; MAX_CHANNELS];
+                for (c, channel) in self.channels.iter().enumerate() {
+                    state[c] = channel[cell];
+                }
+                let mut sum = [0.0; MAX_CHANNELS];
+                let mut written = [false; MAX_CHANNELS];
+                let mut filling = 0.0;
+                for KernelTaps { kernel, taps } in &kernels {
+                    let source = &self.channels[kernel.source];
+                    let u: f32 = taps
+                        .iter()
+                        .map(|(i, j, weight)| self.load(source, IVec2::new(x + i, y + j)) * weight)
+                        .sum();
+                    if kernel.growth == GrowthFunction::Filling {
+                        filling = u;
+                        continue;
+                    }
+                    let d = kernel.destination;
+                    sum[d] += kernel.weight * growth(kernel, u, state[d], filling);
+                    written[d] = true;
+                }
+                for (c, channel) in next.iter_mut().enumerate() {
+                    if written[c] {
+                        channel[cell] = integrate(&self.params, state[c], sum[c]);
+                    }
+                }
+            }
+        }
+        self.channels = next;
+        self.clear_walls();
+    }
+
+    /// The state as the GPU would read it back, see [`StateSnapshot`].
+    pub fn snapshot(&self, step: u64) -> StateSnapshot {
+        let (format, components) = match self.channels.len() {
+            1 => (TextureFormat::R32Float, 1),
+            _ => (TextureFormat::Rgba32Float, 4),
+        };
+        let cells = (self.size.x * self.size.y) as usize;
+        let mut data = Vec::with_capacity(cells * components * 4);
+        for cell in 0..cells {
+            for c in 0..components {
+                let value = self.channels.get(c).map_or(0.0, |channel| channel[cell]);
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        StateSnapshot {
+            size: self.size,
+            format,
+            data,
+            step,
+        }
+    }
+
+    /// Value of `channel` at `coord`, which may lie outside of the grid, like
+    /// `load` in `grid.wgsl`.
+    fn load(&self, channel: &[f32], coord: IVec2) -> f32 {
+        let size = self.size.as_ivec2();
+        let inside = coord.cmpge(IVec2::ZERO).all() && coord.cmplt(size).all();
+        let coord = match self.params.boundary {
+            Boundary::Zero if !inside => return 0.0,
+            Boundary::Clamped | Boundary::Zero => coord.clamp(IVec2::ZERO, size - 1),
+            Boundary::Reflecting => IVec2::new(mirror(coord.x, size.x), mirror(coord.y, size.y)),
+            Boundary::Periodic => coord.rem_euclid(size),
+        };
+        channel[(coord.y * size.x + coord.x) as usize]
+    }
+
+    fn clear_walls(&mut self) {
+        for channel in &mut self.channels {
+            for (value, solid) in channel.iter_mut().zip(&self.solid) {
+                if *solid {
+                    *value = 0.0;
+                }
+            }
+        }
+    }
+}
+
+/// Growth rate of `kernel` for the potential `u` of a cell in state `a`;
+/// `filling` is the potential of the last [`GrowthFunction::Filling`] kernel.
+pub fn growth(kernel: &ChannelKernel, u: f32, a: f32, filling: f32) -> f32 {
+    let (m, s) = (kernel.mu, kernel.sigma);
+    match kernel.growth {
+        GrowthFunction::Intervals { birth, survival } => {
+            let (low, high) = if a > 0.5 { survival } else { birth };
+            if u >= low && u <= high {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+        GrowthFunction::SmoothLife {
+            birth,
+            death,
+            alpha_n,
+            alpha_m,
+        } => {
+            let alive = sigmoid(filling, 0.5, alpha_m);
+            let low = birth.0 + (death.0 - birth.0) * alive;
+            let high = birth.1 + (death.1 - birth.1) * alive;
+            2.0 * sigmoid(u, low, alpha_n) * (1.0 - sigmoid(u, high, alpha_n)) - 1.0
+        }
+        GrowthFunction::Polynomial => (1.0 - (u - m) * (u - m) / (9.0 * s * s)).max(0.0).powi(4) * 2.0 - 1.0,
+        GrowthFunction::Step => {
+            if (u - m).abs() <= s {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+        GrowthFunction::StateModulated => bell(u * (1.0 + (a - 0.5) * 0.2), m, s) * 2.0 - 1.0,
+        // the shader falls back to the Gaussian for filling kernels as well
+        GrowthFunction::Gaussian | GrowthFunction::Filling => bell(u, m, s) * 2.0 - 1.0,
+    }
+}
+
+/// Advances a channel in state `a` by the summed growth `g`.
+pub fn integrate(params: &LeniaParams, a: f32, g: f32) -> f32 {
+    match params.integrator {
+        Integrator::SoftClip => soft_clip(a + params.dt * g),
+        Integrator::Asymptotic => a + params.dt * (g * 0.5 + 0.5 - a),
+        Integrator::Euler => (a + params.dt * g).clamp(0.0, 1.0),
+    }
+}
+
+/// Like `soft_clip` in the shader: the identity but for cubic corners easing into 0 and 1.
+fn soft_clip(x: f32) -> f32 {
+    let w = SOFT_CLIP_WIDTH;
+    let (low, high) = (x.clamp(0.0, 1.0), (1.0 - x).clamp(0.0, 1.0));
+    if low < w {
+        low * low * (2.0 * w - low) / (w * w)
+    } else if high < w {
+        1.0 - high * high * (2.0 * w - high) / (w * w)
+    } else {
+        x
+    }
+}
+
+fn bell(x: f32, mu: f32, sigma: f32) -> f32 {
+    (-((x - mu) * (x - mu)) / (2.0 * sigma * sigma)).exp()
+}
+
+fn sigmoid(x: f32, a: f32, alpha: f32) -> f32 {
+    1.0 / (1.0 + (-(x - a) * 4.0 / alpha).exp())
+}
+
+fn hash(p: Vec2) -> f32 {
+    let p = p.dot(Vec2::new(127.1, 311.7));
+    -1.0 + 2.0 * ((p.sin() * 43_758.547).rem_euclid(1.0))
+}
+
+/// Mirrors `x` back into `[0, n)`, repeating the edge cell.
+fn mirror(x: i32, n: i32) -> i32 {
+    let m = x.rem_euclid(2 * n);
+    if m >= n {
+        2 * n - 1 - m
+    } else {
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lenia::{KernelCore, LeniaKernel};
+
+    fn params(integrator: Integrator, dt: f32) -> LeniaParams {
+        LeniaParams {
+            dt,
+            integrator,
+            ..default()
+        }
+    }
+
+    fn kernel(growth: GrowthFunction) -> ChannelKernel {
+        ChannelKernel {
+            mu: 0.15,
+            sigma: 0.015,
+            growth,
+            ..default()
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "expected {expected}, got {actual}");
+    }
+
+    /// Growth without a filling kernel.
+    fn growth_of(kernel: &ChannelKernel, u: f32, a: f32) -> f32 {
+        growth(kernel, u, a, 0.0)
+    }
+
+    #[test]
+    fn integrators_keep_the_state_without_growth() {
+        for integrator in [Integrator::Euler, Integrator::SoftClip] {
+            for a in [0.0, 0.5, 1.0] {
+                let next = integrate(&params(integrator, 0.1), a, 0.0);
+                assert!((next - a).abs() < 1e-6, "{integrator:?}: {a} became {next}");
+            }
+        }
+    }
+
+    #[test]
+    fn euler_clips_into_the_unit_interval() {
+        let params = params(Integrator::Euler, 0.1);
+        assert_close(integrate(&params, 0.5, 1.0), 0.6);
+        assert_close(integrate(&params, 0.95, 1.0), 1.0);
+        assert_close(integrate(&params, 0.05, -1.0), 0.0);
+    }
+
+    #[test]
+    fn soft_clip_rounds_off_the_corners() {
+        let params = params(Integrator::SoftClip, 0.1);
+        assert_close(integrate(&params, 0.5, 1.0), 0.6);
+        assert_close(integrate(&params, 0.98, 1.0), 1.0);
+        assert_close(integrate(&params, 0.05, -1.0), 0.0);
+        // 0.02^2 (0.1 - 0.02) / 0.05^2 inside the corners
+        assert_close(integrate(&params, 0.02, 0.0), 0.0128);
+        assert_close(integrate(&params, 0.98, 0.0), 0.9872);
+        // and continuous where they meet the identity
+        assert_close(integrate(&params, SOFT_CLIP_WIDTH, 0.0), SOFT_CLIP_WIDTH);
+    }
+
+    #[test]
+    fn asymptotic_relaxes_towards_the_target() {
+        let params = params(Integrator::Asymptotic, 0.1);
+        assert_close(integrate(&params, 0.5, 1.0), 0.55);
+        assert_close(integrate(&params, 0.2, -1.0), 0.18);
+        assert_close(integrate(&params, 0.5, 0.0), 0.5);
+    }
+
+    #[test]
+    fn bell_shaped_growth() {
+        for growth in [GrowthFunction::Gaussian, GrowthFunction::Filling] {
+            let kernel = kernel(growth);
+            assert_close(growth_of(&kernel, 0.15, 0.0), 1.0);
+            // 2 exp(-1/2) - 1 one sigma off
+            assert_close(growth_of(&kernel, 0.165, 0.0), 0.213_061_3);
+        }
+        // the potential is scaled by 1 + 0.2 (a - 0.5)
+        let kernel = kernel(GrowthFunction::StateModulated);
+        assert_close(growth_of(&kernel, 0.165, 0.5), 0.213_061_3);
+        assert_close(growth_of(&kernel, 0.15 / 1.1, 1.0), 1.0);
+    }
+
+    #[test]
+    fn polynomial_and_step_growth() {
+        let kernel = kernel(GrowthFunction::Polynomial);
+        assert_close(growth_of(&kernel, 0.15, 0.0), 1.0);
+        // 2 (1 - 1.5^2 / 9)^4 - 1
+        assert_close(growth_of(&kernel, 0.1725, 0.0), -0.367_187_5);
+        assert_close(growth_of(&kernel, 0.3, 0.0), -1.0);
+
+        let kernel = self::kernel(GrowthFunction::Step);
+        assert_close(growth_of(&kernel, 0.16, 0.0), 1.0);
+        assert_close(growth_of(&kernel, 0.18, 0.0), -1.0);
+    }
+
+    #[test]
+    fn interval_growth() {
+        let kernel = kernel(GrowthFunction::Intervals {
+            birth: (0.3, 0.4),
+            survival: (0.2, 0.5),
+        });
+        assert_close(growth_of(&kernel, 0.35, 0.0), 1.0);
+        assert_close(growth_of(&kernel, 0.25, 0.0), -1.0);
+        assert_close(growth_of(&kernel, 0.25, 1.0), 1.0);
+        assert_close(growth_of(&kernel, 0.5, 1.0), 1.0);
+        assert_close(growth_of(&kernel, 0.6, 1.0), -1.0);
+    }
+
+    #[test]
+    fn smooth_life_growth() {
+        let kernel = kernel(GrowthFunction::SmoothLife {
+            birth: (0.278, 0.365),
+            death: (0.267, 0.445),
+            alpha_n: 0.028,
+            alpha_m: 0.147,
+        });
+        // half filled, the interval is halfway between birth and death,
+        // [0.2725, 0.405], and its lower edge is half way up
+        assert_close(growth(&kernel, 0.2725, 0.0, 0.5), 0.0);
+        // the sigmoid edges only approach the plateaus
+        assert!(growth(&kernel, 0.34, 0.0, 0.5) > 0.999);
+        assert!(growth(&kernel, 0.1, 0.0, 0.5) < -0.999);
+        assert!(growth(&kernel, 0.6, 0.0, 0.5) < -0.999);
+        // fully filled, the interval is the death interval
+        assert!(growth(&kernel, 0.4, 0.0, 1.0) > 0.99);
+        assert!(growth(&kernel, 0.4, 0.0, 0.0) < -0.98);
+    }
+
+    #[test]
+    fn step_matches_a_direct_convolution() {
+        let size = UVec2::new(6, 5);
+        let ring = LeniaKernel {
+            radius: 2,
+            rings: vec![1.0],
+            core: KernelCore::Polynomial { alpha: 4.0 },
+        };
+        let params = LeniaParams {
+            dt: 0.1,
+            integrator: Integrator::Euler,
+            boundary: Boundary::Periodic,
+            kernels: vec![
+                ChannelKernel {
+                    mu: 0.3,
+                    sigma: 0.1,
+                    kernel: ring.clone(),
+                    ..default()
+                },
+                ChannelKernel {
+                    destination: 1,
+                    weight: 0.5,
+                    mu: 0.3,
+                    sigma: 0.1,
+                    kernel: ring.clone(),
+                    ..default()
+                },
+            ],
+        };
+        let mut lenia = CpuLenia::new(params, size);
+        for (c, channel) in lenia.channels.iter_mut().enumerate() {
+            for (cell, value) in channel.iter_mut().enumerate() {
+                *value = ((cell * 7 + c * 3) % 5) as f32 / 4.0;
+            }
+        }
+        let before = lenia.channels.clone();
+        lenia.step();
+
+        let (width, height) = (size.x as i32, size.y as i32);
+        let weights = ring.weights();
+        for y in 0..height {
+            for x in 0..width {
+                let mut u = 0.0;
+                for j in -2..=2 {
+                    for i in -2..=2 {
+                        let neighbour = ((y + j).rem_euclid(height) * width + (x + i).rem_euclid(width)) as usize;
+                        u += weights[((j + 2) * 5 + i + 2) as usize] * before[0][neighbour];
+                    }
+                }
+                let g = 2.0 * (-(u - 0.3) * (u - 0.3) / (2.0 * 0.1 * 0.1)).exp() - 1.0;
+                let cell = (y * width + x) as usize;
+                assert_close(lenia.channels[0][cell], (before[0][cell] + 0.1 * g).clamp(0.0, 1.0));
+                assert_close(lenia.channels[1][cell], (before[1][cell] + 0.1 * 0.5 * g).clamp(0.0, 1.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+use std::ops::RangeInclusive;
+
+use super::{ChannelKernel, GrowthFunction, Integrator, KernelCore, LeniaKernel, LeniaParams};
+
+/// Larger-than-Life: a binary automaton over a disk shaped neighbourhood,
+/// the step between Life and Lenia. Cells are born or survive when the number
+/// of live cells around them, the cell itself included, lies within the
+/// interval for their state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LargerThanLife {
+    /// The neighbourhood holds the cells closer than this to the centre.
+    pub range: i32,
+    pub birth: RangeInclusive<u32>,
+    pub survival: RangeInclusive<u32>,
+}
+
+impl Default for LargerThanLife {
+    /// Bosco's rule, `R5,B34..45,S34..58` on the 11x11 box, with its
+    /// intervals scaled to the 69 cells of the disk.
+    fn default() -> Self {
+        Self {
+            range: 5,
+            birth: 19..=26,
+            survival: 19..=33,
+        }
+    }
+}
+
+/// SmoothLife, after Rafler: the continuous counterpart of Life, comparing the
+/// filling `m` of an inner disk with the filling `n` of the ring around it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmoothLife {
+    /// Outer radius of the ring, three times the radius of the inner disk.
+    pub outer_radius: i32,
+    /// Interval of `n` in which a dead cell comes alive.
+    pub birth: (f32, f32),
+    /// Interval of `n` in which a live cell stays alive.
+    pub death: (f32, f32),
+    /// Smoothness of the interval edges in `n`.
+    pub alpha_n: f32,
+    /// Smoothness of the dead to alive transition in `m`.
+    pub alpha_m: f32,
+    /// Time step; 1 gives the discrete time rule `a = s(n, m)`, smaller
+    /// steps relax towards it.
+    pub dt: f32,
+}
+
+impl Default for SmoothLife {
+    /// The parameters of Rafler's paper, on a smaller radius.
+    fn default() -> Self {
+        Self {
+            outer_radius: 12,
+            birth: (0.278, 0.365),
+            death: (0.267, 0.445),
+            alpha_n: 0.028,
+            alpha_m: 0.147,
+            dt: 1.0,
+        }
+    }
+}
+
+impl LeniaParams {
+    /// The default rule of a family by name: `larger-than-life` or `smooth-life`.
+    pub fn family(name: &str) -> Result<Self, String> {
+        match name {
+            "larger-than-life" => Ok(Self::larger_than_life(&LargerThanLife::default())),
+            "smooth-life" => Ok(Self::smooth_life(&SmoothLife::default())),
+            family => Err(format!("expected larger-than-life or smooth-life, got `{family}`")),
+        }
+    }
+
+    /// A [`LargerThanLife`] rule on the first channel. The neighbourhood is a
+    /// flat kernel, so the potential is the fraction of live cells in it.
+    pub fn larger_than_life(rule: &LargerThanLife) -> Self {
+        let kernel = LeniaKernel {
+            radius: rule.range,
+            rings: vec![1.0],
+            core: KernelCore::Flat,
+        };
+        // the weights are normalised, so a count is a fraction of the cells
+        let cells = kernel.weights().iter().filter(|weight| **weight > 0.0).count() as f32;
+        let fraction = |counts: &RangeInclusive<u32>| {
+            ((*counts.start() as f32 - 0.5) / cells, (*counts.end() as f32 + 0.5) / cells)
+        };
+        Self {
+            // each step replaces the state by 0 or 1
+            dt: 1.0,
+            integrator: Integrator::Euler,
+            kernels: vec![ChannelKernel {
+                growth: GrowthFunction::Intervals {
+                    birth: fraction(&rule.birth),
+                    survival: fraction(&rule.survival),
+                },
+                kernel,
+                ..default()
+            }],
+            ..default()
+        }
+    }
+
+    /// A [`SmoothLife`] rule on the first channel: a [`GrowthFunction::Filling`]
+    /// kernel over the inner disk followed by a [`GrowthFunction::SmoothLife`]
+    /// kernel over the ring.
+    pub fn smooth_life(rule: &SmoothLife) -> Self {
+        let inner = LeniaKernel {
+            radius: (rule.outer_radius / 3).max(1),
+            rings: vec![1.0],
+            core: KernelCore::Flat,
+        };
+        let outer = LeniaKernel {
+            radius: rule.outer_radius,
+            rings: vec![0.0, 1.0, 1.0],
+            core: KernelCore::Flat,
+        };
+        Self {
+            dt: rule.dt,
+            // relaxes towards s(n, m), which is the new state at dt = 1
+            integrator: Integrator::Asymptotic,
+            kernels: vec![
+                ChannelKernel {
+                    growth: GrowthFunction::Filling,
+                    kernel: inner,
+                    ..default()
+                },
+                ChannelKernel {
+                    growth: GrowthFunction::SmoothLife {
+                        birth: rule.birth,
+                        death: rule.death,
+                        alpha_n: rule.alpha_n,
+                        alpha_m: rule.alpha_m,
+                    },
+                    kernel: outer,
+                    ..default()
+                },
+            ],
+            ..default()
+        }
+    }
+}
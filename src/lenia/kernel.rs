@@ -0,0 +1,206 @@
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+use super::{KernelCore, LeniaKernel};
+
+impl KernelCore {
+    /// Value of the core at `r` in `[0, 1)`.
+    pub fn value(self, r: f32) -> f32 {
+        match self {
+            KernelCore::Exponential => (4.0 - 1.0 / (r * (1.0 - r))).exp(),
+            KernelCore::Polynomial { alpha } => (4.0 * r * (1.0 - r)).powf(alpha),
+            KernelCore::Rectangular => {
+                if (0.25..=0.75).contains(&r) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            KernelCore::Gaussian { rho, omega } => bell(r, rho, omega),
+            KernelCore::Flat => 1.0,
+        }
+    }
+}
+
+impl LeniaKernel {
+    /// Height of the kernel at distance `r` from the centre, in units of the radius.
+    pub fn value(&self, r: f32) -> f32 {
+        if r >= 1.0 || self.rings.is_empty() {
+            return 0.0;
+        }
+        let band = r * self.rings.len() as f32;
+        let ring = (band as usize).min(self.rings.len() - 1);
+        self.rings[ring] * self.core.value(band.fract())
+    }
+
+    /// Weights of the (2R+1)^2 taps around a cell, row by row, normalised to sum to one.
+    pub fn weights(&self) -> Vec<f32> {
+        let radius = self.radius.max(1);
+        let mut weights = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+        for j in -radius..=radius {
+            for i in -radius..=radius {
+                let r = ((i * i + j * j) as f32).sqrt() / radius as f32;
+                weights.push(self.value(r));
+            }
+        }
+        let total: f32 = weights.iter().sum();
+        if total > 0.0 {
+            for weight in &mut weights {
+                *weight /= total;
+            }
+        }
+        weights
+    }
+
+    /// 2D Fourier transform of the kernel wrapped onto a periodic grid of
+    /// `size` cells, row by row. Both sides of `size` must be powers of two.
+    ///
+    /// The `1 / (width * height)` factor of the inverse transform is folded in,
+    /// so the shader gets the convolution by multiplying spectra and running
+    /// an unscaled inverse FFT.
+    pub fn spectrum(&self, size: UVec2) -> Vec<Vec2> {
+        let (width, height) = (size.x as usize, size.y as usize);
+        let radius = self.radius.max(1);
+        let scale = 1.0 / (width * height) as f32;
+        let mut spectrum = vec![Vec2::ZERO; width * height];
+        let taps = (-radius..=radius).flat_map(|j| (-radius..=radius).map(move |i| (i, j)));
+        for ((i, j), weight) in taps.zip(self.weights()) {
+            let x = i.rem_euclid(width as i32) as usize;
+            let y = j.rem_euclid(height as i32) as usize;
+            spectrum[y * width + x].x += weight * scale;
+        }
+
+        for row in spectrum.chunks_mut(width) {
+            fft(row);
+        }
+        let mut column = vec![Vec2::ZERO; height];
+        for x in 0..width {
+            for (y, value) in column.iter_mut().enumerate() {
+                *value = spectrum[y * width + x];
+            }
+            fft(&mut column);
+            for (y, value) in column.iter().enumerate() {
+                spectrum[y * width + x] = *value;
+            }
+        }
+        spectrum
+    }
+}
+
+/// Kernel data derived from a list of [`LeniaKernel`]s, kept until the list
+/// changes so that only parameter changes touching the kernels regenerate it.
+#[derive(Clone, Default)]
+pub struct KernelCache {
+    kernels: Vec<LeniaKernel>,
+    weights: Vec<f32>,
+    offsets: Vec<u32>,
+    spectra: Option<(UVec2, Vec<Vec2>)>,
+}
+
+impl KernelCache {
+    fn set_kernels(&mut self, kernels: &[LeniaKernel]) {
+        if self.kernels != kernels {
+            self.kernels = kernels.to_vec();
+            self.weights.clear();
+            self.offsets.clear();
+            for kernel in kernels {
+                self.offsets.push(self.weights.len() as u32);
+                self.weights.extend(kernel.weights());
+            }
+            self.spectra = None;
+        }
+    }
+
+    /// [`LeniaKernel::weights`] of all kernels back to back, and the offset
+    /// of the weights of each kernel.
+    pub fn weights(&mut self, kernels: &[LeniaKernel]) -> (&[f32], &[u32]) {
+        self.set_kernels(kernels);
+        (&self.weights, &self.offsets)
+    }
+
+    /// [`LeniaKernel::spectrum`] of all kernels back to back.
+    pub fn spectra(&mut self, kernels: &[LeniaKernel], size: UVec2) -> &[Vec2] {
+        self.set_kernels(kernels);
+        if !matches!(&self.spectra, Some((cached_size, _)) if *cached_size == size) {
+            let spectra = kernels.iter().flat_map(|kernel| kernel.spectrum(size)).collect();
+            self.spectra = Some((size, spectra));
+        }
+        &self.spectra.as_ref().unwrap().1
+    }
+}
+
+fn bell(x: f32, mu: f32, sigma: f32) -> f32 {
+    (-((x - mu) * (x - mu)) / (2.0 * sigma * sigma)).exp()
+}
+
+/// In place forward FFT of complex values stored as `(re, im)`, radix 2, the
+/// same as `fft_butterflies` in `lenia.compute.wgsl`.
+fn fft(data: &mut [Vec2]) {
+    let n = data.len();
+    debug_assert!(n.is_power_of_two());
+    let bits = n.trailing_zeros();
+    if bits == 0 {
+        return;
+    }
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    let mut half = 1;
+    while half < n {
+        for start in (0..n).step_by(2 * half) {
+            for k in 0..half {
+                let angle = -PI * k as f32 / half as f32;
+                let w = Vec2::new(angle.cos(), angle.sin());
+                let a = data[start + k];
+                let b = data[start + k + half];
+                let t = Vec2::new(w.x * b.x - w.y * b.y, w.x * b.y + w.y * b.x);
+                data[start + k] = a + t;
+                data[start + k + half] = a - t;
+            }
+        }
+        half *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spectrum_is_the_transform_of_the_weights() {
+        let kernel = LeniaKernel {
+            radius: 2,
+            rings: vec![1.0, 0.5],
+            core: KernelCore::Polynomial { alpha: 4.0 },
+        };
+        let size = UVec2::new(8, 4);
+        let (width, height) = (size.x as i32, size.y as i32);
+        let spectrum = kernel.spectrum(size);
+        let weights = kernel.weights();
+
+        // the discrete Fourier transform of the weights wrapped onto the grid,
+        // summed tap by tap
+        for ky in 0..height {
+            for kx in 0..width {
+                let mut expected = Vec2::ZERO;
+                for j in -2..=2_i32 {
+                    for i in -2..=2_i32 {
+                        let weight = weights[((j + 2) * 5 + i + 2) as usize];
+                        let (x, y) = (i.rem_euclid(width), j.rem_euclid(height));
+                        let angle = -2.0 * PI * ((kx * x) as f32 / width as f32 + (ky * y) as f32 / height as f32);
+                        expected += weight * Vec2::new(angle.cos(), angle.sin());
+                    }
+                }
+                expected /= (width * height) as f32;
+                let actual = spectrum[(ky * width + kx) as usize];
+                assert!(
+                    (actual - expected).length() < 1e-6,
+                    "({kx}, {ky}): expected {expected}, got {actual}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::{
+    prelude::*,
+    render::{extract_resource::ExtractResource, render_resource::*},
+};
+use std::{fmt, str::FromStr};
+
+use crate::simulation::{Boundary, ComputeSimulation, ComputeSimulationPlugin, UpdatePass};
+pub use uniforms::LifeRule;
+
+#[derive(Default)]
+pub struct LifeComputePlugin(pub LifeRule);
+
+impl Plugin for LifeComputePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LifeParams {
+            rule: self.0,
+            ..default()
+        })
+        .add_plugins(ComputeSimulationPlugin(Life));
+    }
+}
+
+/// Life-like cellular automaton on the Moore neighbourhood: a discrete
+/// baseline to compare the continuous simulations against. The single
+/// state texture holds 1 for live cells and 0 for dead ones.
+#[derive(Resource, Clone, Default)]
+pub struct Life;
+
+impl ComputeSimulation for Life {
+    type Params = LifeParams;
+    type Bindings = LifeBindings;
+
+    const NAME: &'static str = "life";
+    const SHADER: &'static str = "shaders/game_of_life.wgsl";
+    const UNIT_STATE: bool = true;
+
+    fn textures(&self, _params: &LifeParams) -> Vec<TextureFormat> {
+        vec![TextureFormat::Rgba8Unorm]
+    }
+
+    fn boundary(params: &LifeParams) -> Boundary {
+        params.boundary
+    }
+
+    fn set_boundary(params: &mut LifeParams, boundary: Boundary) {
+        params.boundary = boundary;
+    }
+
+    fn update_passes(&self, _params: &LifeParams, _size: UVec2) -> Vec<UpdatePass> {
+        vec![UpdatePass::new("update")]
+    }
+
+    fn bindings(&mut self, params: &LifeParams, _size: UVec2) -> LifeBindings {
+        LifeBindings { rule: params.rule }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, ExtractResource)]
+pub struct LifeParams {
+    pub rule: LifeRule,
+    pub boundary: Boundary,
+}
+
+impl Default for LifeParams {
+    fn default() -> Self {
+        Self {
+            rule: LifeRule::default(),
+            boundary: Boundary::Periodic,
+        }
+    }
+}
+
+mod uniforms {
+    // see `uniforms` in simulation.rs
+    #![allow(dead_code)]
+
+    use bevy::render::render_resource::ShaderType;
+
+    /// An outer totalistic rule in B/S notation, e.g. `B3/S23` for Conway's
+    /// Game of Life or `B36/S23` for HighLife. Bit `n` of each mask is set when
+    /// `n` live neighbours give birth to a dead cell or keep a live one alive.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, ShaderType)]
+    pub struct LifeRule {
+        pub birth: u32,
+        pub survival: u32,
+    }
+}
+
+impl Default for LifeRule {
+    fn default() -> Self {
+        Self {
+            birth: 1 << 3,
+            survival: 1 << 2 | 1 << 3,
+        }
+    }
+}
+
+/// Parses `B<digits>/S<digits>`, in either order and either case.
+impl FromStr for LifeRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mask = |digits: &str| {
+            digits.chars().try_fold(0u32, |mask, digit| match digit.to_digit(10) {
+                Some(count) if count <= 8 => Ok(mask | 1 << count),
+                _ => Err(format!("invalid neighbour count `{digit}` in `{s}`")),
+            })
+        };
+        let (mut birth, mut survival) = (None, None);
+        for part in s.trim().split('/') {
+            let mut chars = part.chars();
+            match chars.next().map(|c| c.to_ascii_uppercase()) {
+                Some('B') if birth.is_none() => birth = Some(mask(chars.as_str())?),
+                Some('S') if survival.is_none() => survival = Some(mask(chars.as_str())?),
+                _ => return Err(format!("expected B<counts>/S<counts>, got `{s}`")),
+            }
+        }
+        match (birth, survival) {
+            (Some(birth), Some(survival)) => Ok(Self { birth, survival }),
+            _ => Err(format!("expected B<counts>/S<counts>, got `{s}`")),
+        }
+    }
+}
+
+impl fmt::Display for LifeRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = |mask: u32| {
+            (0..=8)
+                .filter(|count| mask & 1 << count != 0)
+                .map(|count| count.to_string())
+                .collect::<String>()
+        };
+        write!(f, "B{}/S{}", counts(self.birth), counts(self.survival))
+    }
+}
+
+#[derive(AsBindGroup)]
+pub struct LifeBindings {
+    #[uniform(0)]
+    rule: LifeRule,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_conway() {
+        assert_eq!("B3/S23".parse::<LifeRule>(), Ok(LifeRule::default()));
+        assert_eq!(" s23/b3 ".parse::<LifeRule>(), Ok(LifeRule::default()));
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for rule in ["B36/S23", "B0/S", "B/S012345678", "B1357/S1357"] {
+            assert_eq!(rule.parse::<LifeRule>().unwrap().to_string(), rule);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        for rule in ["B9/S23", "B3", "S23", "B3/S23/S4", "X3/S23", "B3/Sa", ""] {
+            assert!(rule.parse::<LifeRule>().is_err(), "accepted `{rule}`");
+        }
+    }
+}
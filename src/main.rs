@@ -3,15 +3,31 @@ use bevy::{
     window::{Window, WindowPlugin},
 };
 mod ui;
+mod batch;
+mod brush;
+mod camera;
+mod simulation;
 mod lenia;
 mod fluid;
 mod flow_lenia;
+mod life;
+mod obstacle;
+mod pattern;
+mod switcher;
 
+use crate::pattern::InitialPattern;
+use crate::simulation::GridSize;
+use crate::ui::catalogue::CataloguePlugin;
 use crate::ui::fps::FpsPlugin;
 
 fn main() {
-    App::new()
-        .insert_resource(ClearColor(Color::NONE))
+    // `--headless <config.json>` runs a batch without a window, see `batch::BatchConfig`
+    if let Some(path) = arg_value("--headless") {
+        std::process::exit(batch::run(&path));
+    }
+    let mut app = App::new();
+    app.insert_resource(ClearColor(Color::NONE))
+        .insert_resource(grid_size_from_args())
         .add_plugins((
                 DefaultPlugins.set(WindowPlugin {
                     primary_window: Some(Window {
@@ -25,10 +41,87 @@ fn main() {
                     ..default()
                 }),
                 FpsPlugin,
-                lenia::LeniaComputePlugin,
-                // fluid::FluidComputePlugin,
-                // flow_lenia::FlowLeniaComputePlugin,
-        ))
-        .run();
+                camera::CameraControlPlugin,
+                CataloguePlugin::default(),
+                obstacle::ObstaclePlugin {
+                    path: arg_value("--mask"),
+                },
+                lenia::LeniaComputePlugin(lenia::Lenia::with_precision(lenia_precision_from_args())),
+                flow_lenia::FlowLeniaComputePlugin,
+                fluid::FluidComputePlugin,
+                life::LifeComputePlugin(life_rule_from_args()),
+                brush::BrushPlugin::<lenia::Lenia>::default(),
+                brush::BrushPlugin::<flow_lenia::FlowLenia>::default(),
+                brush::BrushPlugin::<fluid::Fluid>::default(),
+                brush::BrushPlugin::<life::Life>::default(),
+                switcher::SwitcherPlugin,
+        ));
+    // `--pattern <asset path>` starts Lenia from a PNG, CSV or RLE pattern
+    if let Some(path) = arg_value("--pattern") {
+        let pattern = app.world.resource::<AssetServer>().load(path);
+        app.insert_resource(InitialPattern::<lenia::Lenia>::new(pattern, default()));
+    }
+    // `--family larger-than-life` or `--family smooth-life` runs Lenia with
+    // the default rule of one of the families between Life and Lenia
+    if let Some(family) = arg_value("--family") {
+        let params = lenia::LeniaParams::family(&family).unwrap_or_else(|err| {
+            eprintln!("--family: {err}");
+            std::process::exit(2);
+        });
+        app.insert_resource(params);
+    }
+    // `--animal <asset path>` runs the first creature of a Lenia JSON file
+    if let Some(path) = arg_value("--animal") {
+        let library = app.world.resource::<AssetServer>().load(path);
+        app.insert_resource(lenia::ActiveAnimal {
+            library,
+            index: 0,
+            placement: default(),
+        });
+    }
+    app.run();
 }
 
+/// Value following the command line flag `name`, if given.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return Some(args.next().unwrap_or_default());
+        }
+    }
+    None
+}
+
+/// Reads the grid size from `--size WIDTHxHEIGHT`, e.g. `--size 1024x768`.
+fn grid_size_from_args() -> GridSize {
+    match arg_value("--size") {
+        Some(value) => value.parse().unwrap_or_else(|err| {
+            eprintln!("--size: {err}");
+            std::process::exit(2);
+        }),
+        None => GridSize::default(),
+    }
+}
+
+/// Reads the precision of the Lenia state from `--precision`, `f16` or `f32`.
+fn lenia_precision_from_args() -> lenia::LeniaPrecision {
+    match arg_value("--precision") {
+        Some(value) => value.parse().unwrap_or_else(|err| {
+            eprintln!("--precision: {err}");
+            std::process::exit(2);
+        }),
+        None => lenia::LeniaPrecision::default(),
+    }
+}
+
+/// Reads the Life rule from `--rule`, e.g. `--rule B36/S23`.
+fn life_rule_from_args() -> life::LifeRule {
+    match arg_value("--rule") {
+        Some(value) => value.parse().unwrap_or_else(|err| {
+            eprintln!("--rule: {err}");
+            std::process::exit(2);
+        }),
+        None => life::LifeRule::default(),
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::{asset::LoadState, prelude::*, render::render_resource::TextureUsages, window::PrimaryWindow};
+
+use crate::simulation::{GridSize, ObstacleMask, OBSTACLE_FORMAT};
+
+/// Radius of the wall brush in grid cells.
+const BRUSH_RADIUS: f32 = 6.0;
+
+/// Loads the [`ObstacleMask`] from an image and lets walls be painted with
+/// the mouse: shift + left button draws walls, shift + right button erases them.
+#[derive(Default)]
+pub struct ObstaclePlugin {
+    /// Asset path of an image whose bright pixels are walls, e.g. `masks/arena.png`.
+    pub path: Option<String>,
+}
+
+/// Mask image still being loaded by the asset server.
+#[derive(Resource)]
+struct PendingMask(Handle<Image>);
+
+impl Plugin for ObstaclePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ObstacleMask>()
+            .add_systems(Update, (load_mask, paint_mask));
+        if let Some(path) = &self.path {
+            let handle = app.world.resource::<AssetServer>().load(path.clone());
+            app.insert_resource(PendingMask(handle));
+        }
+    }
+}
+
+fn load_mask(
+    mut commands: Commands,
+    pending: Option<Res<PendingMask>>,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    mut mask: ResMut<ObstacleMask>,
+) {
+    let Some(pending) = pending else {
+        return;
+    };
+    if asset_server.load_state(&pending.0) == LoadState::Failed {
+        warn!("failed to load the obstacle mask");
+        commands.remove_resource::<PendingMask>();
+        return;
+    }
+    let Some(image) = images.get(&pending.0) else {
+        return;
+    };
+    match image.convert(OBSTACLE_FORMAT) {
+        Some(mut image) => {
+            image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+            images.insert(mask.image.clone(), image);
+            mask.set_changed();
+        }
+        None => warn!("obstacle mask format {:?} is not supported", image.texture_descriptor.format),
+    }
+    commands.remove_resource::<PendingMask>();
+}
+
+fn paint_mask(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    grid: Res<GridSize>,
+    mut images: ResMut<Assets<Image>>,
+    mut mask: ResMut<ObstacleMask>,
+) {
+    if !keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        return;
+    }
+    let value = if buttons.pressed(MouseButton::Left) {
+        u8::MAX
+    } else if buttons.pressed(MouseButton::Right) {
+        0
+    } else {
+        return;
+    };
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    let Some(cell) = grid.cursor_cell(window, camera, camera_transform) else {
+        return;
+    };
+    let Some(image) = images.get_mut(&mask.image) else {
+        return;
+    };
+
+    let size = grid.size.as_vec2();
+    let mask_size = image.size().as_vec2();
+    let scale = mask_size / size;
+    let centre = cell * scale;
+    let radius = BRUSH_RADIUS * scale;
+    let min = (centre - radius).floor().max(Vec2::ZERO).as_uvec2();
+    let max = (centre + radius).ceil().min(mask_size - 1.0);
+    if max.x < 0.0 || max.y < 0.0 {
+        return;
+    }
+    let max = max.as_uvec2();
+    let width = image.width();
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let offset = (Vec2::new(x as f32, y as f32) + 0.5 - centre) / radius;
+            if offset.length_squared() <= 1.0 {
+                image.data[(y * width + x) as usize] = value;
+            }
+        }
+    }
+    mask.set_changed();
+}
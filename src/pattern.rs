@@ -0,0 +1,491 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{CompressedImageFormats, ImageSampler, ImageType},
+    },
+    utils::BoxedFuture,
+};
+use std::{fmt, marker::PhantomData};
+
+use crate::simulation::ComputeSimulation;
+
+/// A block of cell values to seed a simulation with, see [`InitialPattern`].
+///
+/// Loaded from grayscale PNGs, CSV files with one row of comma separated
+/// values per line, or the run length encoded cell strings of the Lenia
+/// reference implementation (`.rle`).
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct PatternAsset {
+    pub size: UVec2,
+    /// Cell values of each channel, row by row from the top left.
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl PatternAsset {
+    /// Decodes the cell string of a Lenia reference pattern, e.g.
+    /// `"3.A$.2B!"`. `.` or `b` is an empty cell, `o` a full one, `A` to `X`
+    /// and `pA` to `yO` the values 1 to 255 in 255ths, each optionally
+    /// prefixed by a repeat count. `$` ends a row and `!` the pattern.
+    pub fn from_rle(cells: &str) -> Result<Self, PatternError> {
+        Self::from_rle_channels(&[cells])
+    }
+
+    /// Decodes one cell string per channel, see [`PatternAsset::from_rle`].
+    pub fn from_rle_channels(channels: &[&str]) -> Result<Self, PatternError> {
+        let decoded = channels.iter().map(|cells| decode_rle(cells)).collect::<Result<Vec<_>, _>>()?;
+        let width = decoded.iter().flatten().map(Vec::len).max().unwrap_or(0);
+        let height = decoded.iter().map(Vec::len).max().unwrap_or(0);
+        let channels = decoded
+            .into_iter()
+            .map(|rows| {
+                let mut values = vec![0.0; width * height];
+                for (y, row) in rows.iter().enumerate() {
+                    values[y * width..y * width + row.len()].copy_from_slice(row);
+                }
+                values
+            })
+            .collect();
+        Ok(Self {
+            size: UVec2::new(width as u32, height as u32),
+            channels,
+        })
+    }
+
+    /// Reads comma separated rows of values in `[0, 1]`, one row per line.
+    pub fn from_csv(text: &str) -> Result<Self, PatternError> {
+        let rows = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                line.split(',')
+                    .map(|value| {
+                        value
+                            .trim()
+                            .parse::<f32>()
+                            .map_err(|_| PatternError::InvalidValue(value.trim().to_string()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut values = vec![0.0; width * rows.len()];
+        for (y, row) in rows.iter().enumerate() {
+            values[y * width..y * width + row.len()].copy_from_slice(row);
+        }
+        Ok(Self {
+            size: UVec2::new(width as u32, rows.len() as u32),
+            channels: vec![values],
+        })
+    }
+
+    /// Reads the luminance of an encoded 8 bit image, e.g. a PNG, as a single
+    /// channel.
+    pub fn from_image_bytes(bytes: &[u8], extension: &str) -> Result<Self, PatternError> {
+        // as sRGB, 8 bit images end up in the one RGBA format that converts back
+        let image = Image::from_buffer(
+            bytes,
+            ImageType::Extension(extension),
+            CompressedImageFormats::NONE,
+            true,
+            ImageSampler::Default,
+            RenderAssetUsages::default(),
+        )
+        .map_err(|err| PatternError::Image(err.to_string()))?;
+        let format = image.texture_descriptor.format;
+        let luminance = image
+            .try_into_dynamic()
+            .map_err(|_| PatternError::Image(format!("unsupported format {format:?}")))?
+            .into_luma8();
+        Ok(Self {
+            size: UVec2::new(luminance.width(), luminance.height()),
+            channels: vec![luminance.into_raw().into_iter().map(|value| value as f32 / 255.0).collect()],
+        })
+    }
+
+    /// Reads a pattern file of the given lowercase extension: `png`, `csv` or `rle`.
+    pub fn from_bytes(bytes: &[u8], extension: &str) -> Result<Self, PatternError> {
+        let text = || String::from_utf8_lossy(bytes).into_owned();
+        match extension {
+            "png" => Self::from_image_bytes(bytes, "png"),
+            "csv" => Self::from_csv(&text()),
+            // Golly style files carry comments and a header line before the cells
+            "rle" => {
+                let cells: String = text()
+                    .lines()
+                    .filter(|line| !line.starts_with('#') && !line.contains('='))
+                    .collect();
+                Self::from_rle(&cells)
+            }
+            _ => Err(PatternError::UnknownExtension(extension.to_string())),
+        }
+    }
+
+    /// The pattern as an `Rgba32Float` image, channel `i` in colour component `i`.
+    pub fn to_image(&self) -> Image {
+        let cells = (self.size.x * self.size.y) as usize;
+        let mut data = Vec::with_capacity(cells * 16);
+        for cell in 0..cells {
+            for channel in 0..4 {
+                let value = self.channels.get(channel).map_or(0.0, |values| values[cell]);
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Image::new(
+            Extent3d {
+                width: self.size.x.max(1),
+                height: self.size.y.max(1),
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            if data.is_empty() { vec![0; 16] } else { data },
+            TextureFormat::Rgba32Float,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+}
+
+/// Rows of cell values in a Lenia reference cell string.
+fn decode_rle(cells: &str) -> Result<Vec<Vec<f32>>, PatternError> {
+    let mut rows = vec![Vec::new()];
+    let mut count = String::new();
+    let mut chars = cells.chars().filter(|c| !c.is_whitespace());
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() {
+            count.push(c);
+            continue;
+        }
+        let repeat = if count.is_empty() { 1 } else { count.parse().unwrap_or(1) };
+        count.clear();
+        let value = match c {
+            '!' => break,
+            '$' => {
+                rows.extend((0..repeat).map(|_| Vec::new()));
+                continue;
+            }
+            '.' | 'b' => 0,
+            'o' => 255,
+            'A'..='X' => c as u32 - 'A' as u32 + 1,
+            'p'..='y' => match chars.next() {
+                Some(low @ 'A'..='X') => (c as u32 - 'p' as u32) * 24 + low as u32 - 'A' as u32 + 25,
+                _ => return Err(PatternError::InvalidValue(c.to_string())),
+            },
+            _ => return Err(PatternError::InvalidValue(c.to_string())),
+        };
+        let row = rows.last_mut().unwrap();
+        row.resize(row.len() + repeat, value.min(255) as f32 / 255.0);
+    }
+    // a trailing `$` does not start another row
+    if rows.len() > 1 && rows.last().is_some_and(Vec::is_empty) {
+        rows.pop();
+    }
+    Ok(rows)
+}
+
+#[derive(Debug)]
+pub enum PatternError {
+    Io(std::io::Error),
+    InvalidValue(String),
+    Image(String),
+    UnknownExtension(String),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatternError::Io(err) => write!(f, "could not read pattern: {err}"),
+            PatternError::InvalidValue(value) => write!(f, "invalid cell value `{value}`"),
+            PatternError::Image(err) => write!(f, "could not decode pattern image: {err}"),
+            PatternError::UnknownExtension(extension) => {
+                write!(f, "unknown pattern extension `{extension}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+impl From<std::io::Error> for PatternError {
+    fn from(err: std::io::Error) -> Self {
+        PatternError::Io(err)
+    }
+}
+
+#[derive(Default)]
+pub struct PatternLoader;
+
+impl AssetLoader for PatternLoader {
+    type Asset = PatternAsset;
+    type Settings = ();
+    type Error = PatternError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<PatternAsset, PatternError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let extension = load_context
+                .path()
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            PatternAsset::from_bytes(&bytes, &extension)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["png", "csv", "rle"]
+    }
+}
+
+/// Where a [`PatternAsset`] lands on the grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PatternPlacement {
+    /// Cell the centre of the pattern is placed on, relative to the centre of the grid.
+    pub position: Vec2,
+    /// Grid cells per pattern cell.
+    pub scale: f32,
+    /// Counterclockwise rotation in radians.
+    pub rotation: f32,
+}
+
+impl Default for PatternPlacement {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            scale: 1.0,
+            rotation: 0.0,
+        }
+    }
+}
+
+pub struct PatternPlugin;
+
+impl Plugin for PatternPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<PatternAsset>()
+            .init_asset_loader::<PatternLoader>();
+    }
+}
+
+/// Pattern `S` starts from. Whenever the simulation is seeded the pattern is
+/// stamped over its first state texture, replacing what the init entry point
+/// wrote there. Changing the resource seeds the simulation again.
+#[derive(Resource)]
+pub struct InitialPattern<S: ComputeSimulation> {
+    pub pattern: Handle<PatternAsset>,
+    pub placement: PatternPlacement,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> InitialPattern<S> {
+    pub fn new(pattern: Handle<PatternAsset>, placement: PatternPlacement) -> Self {
+        Self {
+            pattern,
+            placement,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// The [`InitialPattern`] of `S` uploaded as an image.
+#[derive(Resource)]
+pub(crate) struct PatternImage<S: ComputeSimulation> {
+    pub image: Handle<Image>,
+    pub placement: PatternPlacement,
+    /// Bumped whenever the pattern changes, to seed the simulation again.
+    pub generation: u32,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Clone for PatternImage<S> {
+    fn clone(&self) -> Self {
+        Self {
+            image: self.image.clone(),
+            placement: self.placement,
+            generation: self.generation,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> ExtractResource for PatternImage<S> {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.clone()
+    }
+}
+
+/// Uploads the [`InitialPattern`] once it is loaded, and again when it or its
+/// asset changes.
+pub(crate) fn upload_pattern<S: ComputeSimulation>(
+    mut commands: Commands,
+    initial: Option<Res<InitialPattern<S>>>,
+    patterns: Res<Assets<PatternAsset>>,
+    mut events: EventReader<AssetEvent<PatternAsset>>,
+    mut images: ResMut<Assets<Image>>,
+    current: Option<Res<PatternImage<S>>>,
+) {
+    let Some(initial) = initial else {
+        return;
+    };
+    let reloaded = events
+        .read()
+        .filter(|event| {
+            event.is_loaded_with_dependencies(&initial.pattern) || event.is_modified(&initial.pattern)
+        })
+        .count()
+        > 0;
+    if !initial.is_changed() && !reloaded {
+        return;
+    }
+    // not loaded yet, the load event brings us back here
+    let Some(pattern) = patterns.get(&initial.pattern) else {
+        return;
+    };
+    commands.insert_resource(PatternImage::<S> {
+        image: images.add(pattern.to_image()),
+        placement: initial.placement,
+        generation: current.map_or(1, |current| current.generation + 1),
+        marker: PhantomData,
+    });
+}
+
+/// Stamps a pattern into the running state of `S`, replacing the cells it
+/// covers in the first state texture and leaving the others as they are.
+#[derive(Event)]
+pub struct SpawnPattern<S: ComputeSimulation> {
+    pub pattern: Handle<PatternAsset>,
+    pub placement: PatternPlacement,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> SpawnPattern<S> {
+    pub fn new(pattern: Handle<PatternAsset>, placement: PatternPlacement) -> Self {
+        Self {
+            pattern,
+            placement,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// The latest batch of [`SpawnPattern`]s of `S`, uploaded as images.
+#[derive(Resource)]
+pub(crate) struct PatternSpawns<S: ComputeSimulation> {
+    pub stamps: Vec<(Handle<Image>, PatternPlacement)>,
+    /// Bumped with every batch, so that each is stamped once.
+    pub generation: u32,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Default for PatternSpawns<S> {
+    fn default() -> Self {
+        Self {
+            stamps: Vec::new(),
+            generation: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> ExtractResource for PatternSpawns<S> {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        Self {
+            stamps: source.stamps.clone(),
+            generation: source.generation,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Turns the [`SpawnPattern`]s whose patterns are loaded into the next batch.
+pub(crate) fn upload_spawns<S: ComputeSimulation>(
+    mut events: EventReader<SpawnPattern<S>>,
+    mut waiting: Local<Vec<(Handle<PatternAsset>, PatternPlacement)>>,
+    patterns: Res<Assets<PatternAsset>>,
+    mut images: ResMut<Assets<Image>>,
+    mut spawns: ResMut<PatternSpawns<S>>,
+) {
+    waiting.extend(events.read().map(|spawn| (spawn.pattern.clone(), spawn.placement)));
+    let (ready, pending): (Vec<_>, Vec<_>) =
+        waiting.drain(..).partition(|(pattern, _)| patterns.contains(pattern));
+    *waiting = pending;
+    if ready.is_empty() {
+        // drop the images of the last batch once it went out
+        if !spawns.stamps.is_empty() {
+            spawns.stamps.clear();
+        }
+        return;
+    }
+    spawns.stamps = ready
+        .into_iter()
+        .map(|(pattern, placement)| (images.add(patterns.get(&pattern).unwrap().to_image()), placement))
+        .collect();
+    spawns.generation += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_rle_cells() {
+        let pattern = PatternAsset::from_rle("3.A$.2B$2$o!").unwrap();
+        assert_eq!(pattern.size, UVec2::new(4, 5));
+        let (a, b) = (1.0 / 255.0, 2.0 / 255.0);
+        #[rustfmt::skip]
+        let expected = [
+            0.0, 0.0, 0.0, a,
+            0.0, b, b, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+            1.0, 0.0, 0.0, 0.0,
+        ];
+        assert_eq!(pattern.channels, vec![expected.to_vec()]);
+    }
+
+    #[test]
+    fn decodes_two_letter_rle_values() {
+        let pattern = PatternAsset::from_rle("pAyO$2pB!").unwrap();
+        assert_eq!(pattern.size, UVec2::new(2, 2));
+        assert_eq!(pattern.channels[0], vec![25.0 / 255.0, 1.0, 26.0 / 255.0, 26.0 / 255.0]);
+    }
+
+    #[test]
+    fn rejects_unknown_rle_symbols() {
+        assert!(matches!(PatternAsset::from_rle("2Z!"), Err(PatternError::InvalidValue(_))));
+        assert!(matches!(PatternAsset::from_rle("p!"), Err(PatternError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn reads_csv_rows() {
+        let pattern = PatternAsset::from_csv("# comment\n0, 0.5\n1\n").unwrap();
+        assert_eq!(pattern.size, UVec2::new(2, 2));
+        assert_eq!(pattern.channels, vec![vec![0.0, 0.5, 1.0, 0.0]]);
+        assert!(PatternAsset::from_csv("0,x").is_err());
+    }
+
+    #[test]
+    fn loads_every_registered_extension() {
+        for extension in PatternLoader.extensions() {
+            let result = PatternAsset::from_bytes(b"", extension);
+            assert!(!matches!(result, Err(PatternError::UnknownExtension(_))), "{extension}");
+        }
+        let pattern = PatternAsset::from_bytes(include_bytes!("../assets/masks/arena.png"), "png").unwrap();
+        assert_eq!(pattern.channels[0].len(), (pattern.size.x * pattern.size.y) as usize);
+        assert!(pattern.channels[0].iter().any(|value| *value > 0.0));
+    }
+}
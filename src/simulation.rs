@@ -0,0 +1,1086 @@
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssetUsages,
+        render_asset::RenderAssets,
+        render_graph::{RenderGraph, RenderLabel},
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        texture::{FallbackImage, ImageSampler, TextureFormatPixelInfo},
+        Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+use std::{borrow::Cow, marker::PhantomData, str::FromStr};
+
+use crate::brush::BrushStrokes;
+use crate::pattern::{upload_pattern, upload_spawns, PatternImage, PatternPlugin, PatternSpawns, SpawnPattern};
+
+mod control;
+mod dabs;
+mod node;
+mod readback;
+mod resample;
+mod stamp;
+
+use control::SimulationControlPlugin;
+pub use control::SimulationControl;
+pub(crate) use control::SimulationSteps;
+use dabs::DabPipeline;
+use node::SimulationNode;
+use readback::{map_readbacks, receive_snapshots, PendingReadbacks, SnapshotRequest};
+pub use readback::{SnapshotTaken, StateSnapshot, StateSnapshots};
+use resample::ResamplePipelines;
+use stamp::StampPipelines;
+use uniforms::GridUniform;
+
+const WORKGROUP_SIZE: u32 = 8;
+/// Shader module imported as `simulation::grid`, holding the `@group(2)`
+/// bindings and the boundary aware `load` function.
+const GRID_SHADER: &str = "shaders/grid.wgsl";
+
+/// A grid simulation driven by a chain of compute passes over a set of double
+/// buffered state textures.
+///
+/// The shader sees the state textures in `@group(0)`: texture `i` is readable
+/// at `@binding(2 * i)` as a `texture_2d<f32>` holding the previous pass, and
+/// writable at `@binding(2 * i + 1)` as a storage texture. Every pass has to
+/// write all of the textures it declares in [`UpdatePass::writes`], copying
+/// through the channels it does not change.
+/// [`ComputeSimulation::Bindings`] is bound at `@group(1)` and the [`GridUniform`]
+/// at `@group(2) @binding(0)`; shaders get these from `#import simulation::grid`.
+/// Passes are dispatched in 8x8 workgroups rounded up to cover the grid, so
+/// entry points must skip invocations outside of it, unless the pass asks for
+/// another [`Workgroups`] shape.
+///
+/// What ends up on screen is decoupled from the state: after the update passes
+/// the display entry point reads the latest state and writes the sprite's
+/// colour image, an `rgba8unorm` storage texture at `@group(2) @binding(1)`.
+///
+/// When an [`InitialPattern`](crate::pattern::InitialPattern) is set for the
+/// simulation, it is stamped over the first state texture right after the
+/// init entry point ran. [`SpawnPattern`] events stamp patterns into the
+/// first state texture of the running simulation before the next update,
+/// followed by the dabs of the [`Brush`](crate::brush::Brush).
+///
+/// The [`ObstacleMask`] is bound at `@group(2) @binding(2)`; `is_solid` tells
+/// the shader which cells are walls and `store_display` draws them.
+pub trait ComputeSimulation: Resource + Clone {
+    /// Main world resource with the tunable parameters of the simulation.
+    type Params: Resource + ExtractResource<Source = Self::Params> + Default;
+    /// Resources bound at `@group(1)`, rebuilt whenever `Params` change.
+    type Bindings: AsBindGroup;
+
+    /// Unique name, used to label the render graph node.
+    const NAME: &'static str;
+    /// Asset path of the compute shader.
+    const SHADER: &'static str;
+    /// Entry point that seeds the state once the pipelines are ready.
+    const INIT_ENTRY_POINT: &'static str = "init";
+    /// Entry point that converts the state into the displayed colour image.
+    const DISPLAY_ENTRY_POINT: &'static str = "display";
+    /// Whether the first state texture holds values in `[0, 1]`, so that the
+    /// brush clamps what it paints into that range.
+    const UNIT_STATE: bool = false;
+
+    /// Formats of the state textures, in binding order. Only the
+    /// [`STORAGE_FORMATS`] can be resampled, stamped and painted; with other
+    /// formats those are skipped. When a change of the parameters changes
+    /// them, the textures are reallocated and seeded again.
+    fn textures(&self, params: &Self::Params) -> Vec<TextureFormat>;
+
+    /// Shader defs the pipelines are compiled with, e.g. to match the storage
+    /// texture declarations to the `textures` formats.
+    fn shader_defs(&self, _textures: &[TextureFormat]) -> Vec<ShaderDefVal> {
+        Vec::new()
+    }
+
+    /// How the shaders treat reads outside of the grid.
+    fn boundary(_params: &Self::Params) -> Boundary {
+        Boundary::Periodic
+    }
+
+    /// Changes the boundary in `params`, for simulations that let it change.
+    fn set_boundary(_params: &mut Self::Params, _boundary: Boundary) {}
+
+    /// Passes of a single step, in order, on a grid of `size` cells. A frame
+    /// takes as many steps as the [`SimulationControl`](control::SimulationControl)
+    /// asks for.
+    fn update_passes(&self, params: &Self::Params, size: UVec2) -> Vec<UpdatePass>;
+
+    /// Called when the parameters or the grid size change. Takes `&mut self`
+    /// so that expensive derived data can be cached between calls.
+    fn bindings(&mut self, params: &Self::Params, size: UVec2) -> Self::Bindings;
+}
+
+/// An entry point of the simulation shader dispatched over the whole grid.
+#[derive(Clone, Copy, Debug)]
+pub struct UpdatePass {
+    pub entry_point: &'static str,
+    /// Number of back to back dispatches per frame.
+    pub repeat: u32,
+    pub workgroups: Workgroups,
+    /// Mask of the state textures the pass writes, bit `i` for texture `i`,
+    /// all of them unless declared otherwise. Their buffers swap after every
+    /// dispatch; the others are left as they are.
+    pub writes: u32,
+}
+
+impl UpdatePass {
+    pub const fn new(entry_point: &'static str) -> Self {
+        Self {
+            entry_point,
+            repeat: 1,
+            workgroups: Workgroups::Tiles,
+            writes: u32::MAX,
+        }
+    }
+
+    pub const fn repeat(self, repeat: u32) -> Self {
+        Self { repeat, ..self }
+    }
+
+    pub const fn workgroups(self, workgroups: Workgroups) -> Self {
+        Self { workgroups, ..self }
+    }
+
+    /// Writes only the state textures at `textures`, see [`UpdatePass::writes`].
+    pub const fn writes(self, textures: &[usize]) -> Self {
+        let mut writes = 0;
+        let mut i = 0;
+        while i < textures.len() {
+            writes |= 1 << textures[i];
+            i += 1;
+        }
+        Self { writes, ..self }
+    }
+
+    /// Writes none of the state textures, only the bindings.
+    pub const fn bindings_only(self) -> Self {
+        self.writes(&[])
+    }
+}
+
+/// How the workgroups of a pass are laid over the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workgroups {
+    /// One workgroup per 8x8 tile of cells.
+    Tiles,
+    /// One workgroup per row, the row being `workgroup_id.y`.
+    Rows,
+    /// One workgroup per column, the column being `workgroup_id.x`.
+    Columns,
+    /// A single workgroup, for passes that do not touch the cells.
+    Single,
+}
+
+impl Workgroups {
+    fn count(self, size: UVec2) -> (u32, u32) {
+        match self {
+            Workgroups::Tiles => (
+                size.x.div_ceil(WORKGROUP_SIZE),
+                size.y.div_ceil(WORKGROUP_SIZE),
+            ),
+            Workgroups::Rows => (1, size.y),
+            Workgroups::Columns => (size.x, 1),
+            Workgroups::Single => (1, 1),
+        }
+    }
+}
+
+/// Size of the simulation grid in cells.
+///
+/// Changing it reallocates the state textures of every simulation. With
+/// `resample` set the current state is scaled into the new textures,
+/// otherwise the simulations start over from their init entry point.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GridSize {
+    pub size: UVec2,
+    pub resample: bool,
+}
+
+impl Default for GridSize {
+    fn default() -> Self {
+        Self {
+            size: UVec2::new(600, 400),
+            resample: true,
+        }
+    }
+}
+
+impl GridSize {
+    /// Cell under the cursor, counted from the top left corner of the grid.
+    /// May lie outside of the grid.
+    pub fn cursor_cell(&self, window: &Window, camera: &Camera, camera_transform: &GlobalTransform) -> Option<Vec2> {
+        let position = camera.viewport_to_world_2d(camera_transform, window.cursor_position()?)?;
+        // the sprite is centred on the origin with one world unit per cell and y up
+        let size = self.size.as_vec2();
+        Some(Vec2::new(position.x + size.x / 2.0, size.y / 2.0 - position.y))
+    }
+}
+
+/// Parses `WIDTHxHEIGHT`, e.g. `600x400`.
+impl FromStr for GridSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| v.trim().parse::<u32>().ok().filter(|v| *v > 0);
+        match s.split_once('x') {
+            Some((width, height)) => match (parse(width), parse(height)) {
+                (Some(width), Some(height)) => Ok(Self {
+                    size: UVec2::new(width, height),
+                    ..default()
+                }),
+                _ => Err(format!("invalid grid size `{s}`")),
+            },
+            None => Err(format!("expected WIDTHxHEIGHT, got `{s}`")),
+        }
+    }
+}
+
+/// What the cells beyond the edges of the grid hold, see `load` in `grid.wgsl`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Boundary {
+    /// The grid wraps around into a torus.
+    #[default]
+    Periodic = 0,
+    /// Cells beyond an edge repeat the edge cell.
+    Clamped = 1,
+    /// Cells beyond an edge are zero, anything leaving the grid is absorbed.
+    Zero = 2,
+    /// The grid is mirrored at its edges, acting as a solid wall.
+    Reflecting = 3,
+}
+
+impl Boundary {
+    const ALL: [Boundary; 4] = [Boundary::Periodic, Boundary::Clamped, Boundary::Zero, Boundary::Reflecting];
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|boundary| *boundary == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Format of the colour image the display entry point writes.
+const DISPLAY_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+mod uniforms {
+    // The `ShaderType` derive checks every field in a `check` fn it emits next
+    // to the struct and never calls, out of reach of an attribute on the struct.
+    #![allow(dead_code)]
+
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    /// Grid description handed to every pass at `@group(2) @binding(0)`.
+    #[derive(Clone, Copy, Debug, ShaderType)]
+    pub struct GridUniform {
+        pub size: IVec2,
+        pub boundary: u32,
+    }
+}
+
+/// Cells the simulations treat as solid walls, shared by all of them.
+///
+/// A single channel image where texels above one half are solid. It is
+/// stretched over the grid, so it does not need to match [`GridSize`].
+/// Modify the image in place and mark this resource as changed to move walls
+/// at runtime.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct ObstacleMask {
+    pub image: Handle<Image>,
+}
+
+/// Format of the [`ObstacleMask`] image.
+pub const OBSTACLE_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+impl FromWorld for ObstacleMask {
+    fn from_world(world: &mut World) -> Self {
+        let size = world.get_resource::<GridSize>().copied().unwrap_or_default().size;
+        let image = create_obstacle_image(size);
+        Self {
+            image: world.resource_mut::<Assets<Image>>().add(image),
+        }
+    }
+}
+
+/// An empty [`ObstacleMask`] image of `size` texels, kept in the main world
+/// as well so that it can be painted.
+pub fn create_obstacle_image(size: UVec2) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0],
+        OBSTACLE_FORMAT,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+    image
+}
+
+/// Whether `S` runs and is shown. An inactive simulation keeps its state
+/// textures as they are and hides its sprite until it is activated again.
+#[derive(Resource)]
+pub struct SimulationActive<S: ComputeSimulation> {
+    pub active: bool,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Default for SimulationActive<S> {
+    fn default() -> Self {
+        Self {
+            active: true,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> ExtractResource for SimulationActive<S> {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        Self {
+            active: source.active,
+            marker: PhantomData,
+        }
+    }
+}
+
+pub struct ComputeSimulationPlugin<S: ComputeSimulation>(pub S);
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct SimulationLabel(&'static str);
+
+impl<S: ComputeSimulation> Plugin for ComputeSimulationPlugin<S> {
+    fn build(&self, app: &mut App) {
+        // Extract the state textures and the parameters from the main world into the
+        // render world for operation on by the compute shader and display on the sprite.
+        app
+            .insert_resource(self.0.clone())
+            .init_resource::<S::Params>()
+            .init_resource::<GridSize>()
+            .init_resource::<ObstacleMask>()
+            .init_resource::<StateSnapshots<S>>()
+            .init_resource::<PatternSpawns<S>>()
+            .init_resource::<BrushStrokes<S>>()
+            .init_resource::<SimulationActive<S>>()
+            .add_event::<SnapshotTaken<S>>()
+            .add_event::<SpawnPattern<S>>()
+            .add_systems(Startup, setup::<S>)
+            .add_systems(PreUpdate, receive_snapshots::<S>)
+            .add_systems(
+                Update,
+                (
+                    resize::<S>,
+                    release_previous_textures::<S>,
+                    upload_pattern::<S>,
+                    upload_spawns::<S>,
+                    show_active_sprite::<S>,
+                    cycle_boundary::<S>,
+                ),
+            )
+            .add_plugins((
+                ExtractResourcePlugin::<SimulationImages<S>>::default(),
+                ExtractResourcePlugin::<S::Params>::default(),
+                ExtractResourcePlugin::<PatternImage<S>>::default(),
+                ExtractResourcePlugin::<SnapshotRequest<S>>::default(),
+                ExtractResourcePlugin::<PatternSpawns<S>>::default(),
+                ExtractResourcePlugin::<BrushStrokes<S>>::default(),
+                ExtractResourcePlugin::<SimulationActive<S>>::default(),
+            ));
+        if !app.is_plugin_added::<PatternPlugin>() {
+            app.add_plugins(PatternPlugin);
+        }
+        if !app.is_plugin_added::<SimulationControlPlugin>() {
+            app.add_plugins(SimulationControlPlugin);
+        }
+        // the mask is shared, so only the first simulation extracts it
+        if !app.is_plugin_added::<ExtractResourcePlugin<ObstacleMask>>() {
+            app.add_plugins(ExtractResourcePlugin::<ObstacleMask>::default());
+        }
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(self.0.clone())
+            .init_resource::<PendingReadbacks<S>>()
+            .add_systems(
+            Render,
+            (
+                queue_pipelines::<S>.in_set(RenderSet::PrepareResources),
+                prepare_bind_groups::<S>.in_set(RenderSet::PrepareBindGroups),
+                map_readbacks::<S>.in_set(RenderSet::Cleanup),
+            ),
+        );
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(SimulationLabel(S::NAME), SimulationNode::<S>::default());
+        render_graph.add_node_edge(SimulationLabel(S::NAME), bevy::render::graph::CameraDriverLabel,
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<SimulationPipeline<S>>();
+    }
+}
+
+/// Pressing O switches the running simulation to the next [`Boundary`].
+fn cycle_boundary<S: ComputeSimulation>(
+    keys: Res<ButtonInput<KeyCode>>,
+    active: Res<SimulationActive<S>>,
+    mut params: ResMut<S::Params>,
+) {
+    if keys.just_pressed(KeyCode::KeyO) && active.active {
+        let boundary = S::boundary(&params).next();
+        S::set_boundary(&mut params, boundary);
+        info!("{} boundary: {boundary:?}", S::NAME);
+    }
+}
+
+fn create_textures(formats: &[TextureFormat], images: &mut Assets<Image>, size: UVec2) -> Vec<[Handle<Image>; 2]> {
+    formats
+        .iter()
+        .copied()
+        .map(|format| {
+            let mut image = Image::new_fill(
+                Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                &vec![0; format.pixel_size()],
+                format,
+                RenderAssetUsages::RENDER_WORLD,
+            );
+            image.texture_descriptor.usage = TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC
+                | TextureUsages::STORAGE_BINDING
+                | TextureUsages::TEXTURE_BINDING;
+            [images.add(image.clone()), images.add(image)]
+        })
+        .collect()
+}
+
+fn create_display_image(images: &mut Assets<Image>, size: UVec2) -> Handle<Image> {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        DISPLAY_FORMAT,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+    // keep the cells crisp when zoomed in
+    image.sampler = ImageSampler::nearest();
+    images.add(image)
+}
+
+/// Marks the sprite displaying the state of `S`.
+#[derive(Component)]
+pub struct SimulationSprite<S: ComputeSimulation>(PhantomData<fn() -> S>);
+
+fn setup<S: ComputeSimulation>(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    simulation: Res<S>,
+    params: Res<S::Params>,
+    grid: Res<GridSize>,
+) {
+    let formats = simulation.textures(&params);
+    let textures = create_textures(&formats, &mut images, grid.size);
+    let display = create_display_image(&mut images, grid.size);
+
+    commands.spawn((
+        SimulationSprite::<S>(PhantomData),
+        SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(grid.size.as_vec2()),
+                ..default()
+            },
+            texture: display.clone(),
+            ..default()
+        },
+    ));
+    commands.insert_resource(SimulationImages::<S> {
+        textures,
+        formats,
+        display,
+        size: grid.size,
+        previous: Vec::new(),
+        generation: 0,
+        marker: PhantomData,
+    });
+}
+
+fn show_active_sprite<S: ComputeSimulation>(
+    active: Res<SimulationActive<S>>,
+    mut sprites: Query<&mut Visibility, With<SimulationSprite<S>>>,
+) {
+    if !active.is_changed() {
+        return;
+    }
+    for mut visibility in &mut sprites {
+        *visibility = if active.active {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+/// Reallocates the state textures when the [`GridSize`] or the formats the
+/// parameters ask for change.
+fn resize<S: ComputeSimulation>(
+    mut images: ResMut<Assets<Image>>,
+    mut simulation_images: ResMut<SimulationImages<S>>,
+    mut sprites: Query<(&mut Sprite, &mut Handle<Image>), With<SimulationSprite<S>>>,
+    simulation: Res<S>,
+    params: Res<S::Params>,
+    grid: Res<GridSize>,
+) {
+    if !grid.is_changed() && !params.is_changed() {
+        return;
+    }
+    let formats = simulation.textures(&params);
+    let reformat = formats != simulation_images.formats;
+    if simulation_images.size == grid.size && !reformat {
+        return;
+    }
+    let textures = create_textures(&formats, &mut images, grid.size);
+    simulation_images.display = create_display_image(&mut images, grid.size);
+    for (mut sprite, mut texture) in &mut sprites {
+        sprite.custom_size = Some(grid.size.as_vec2());
+        *texture = simulation_images.display.clone();
+    }
+
+    let old_textures = std::mem::replace(&mut simulation_images.textures, textures);
+    // the resample pipelines of other formats are not compiled yet
+    simulation_images.previous = if grid.resample && !reformat {
+        old_textures
+    } else {
+        Vec::new()
+    };
+    simulation_images.formats = formats;
+    simulation_images.size = grid.size;
+    simulation_images.generation += 1;
+}
+
+/// Drops the textures replaced by a resize once they have been extracted for resampling.
+fn release_previous_textures<S: ComputeSimulation>(
+    mut simulation_images: ResMut<SimulationImages<S>>,
+) {
+    if !simulation_images.is_changed() && !simulation_images.previous.is_empty() {
+        simulation_images.previous.clear();
+    }
+}
+
+/// Double buffered state textures of `S`. Each pass reads index `i` of every
+/// texture and writes index `1 - i`; the render node keeps track of which
+/// index holds the latest state.
+#[derive(Resource)]
+pub struct SimulationImages<S: ComputeSimulation> {
+    pub textures: Vec<[Handle<Image>; 2]>,
+    /// Formats of `textures`, see [`ComputeSimulation::textures`].
+    pub formats: Vec<TextureFormat>,
+    /// Colour image shown on the sprite, written by the display entry point.
+    pub display: Handle<Image>,
+    /// Size the textures were allocated with.
+    pub size: UVec2,
+    /// Textures from before the last resize, to resample from.
+    previous: Vec<[Handle<Image>; 2]>,
+    /// Bumped on every reallocation.
+    generation: u32,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Clone for SimulationImages<S> {
+    fn clone(&self) -> Self {
+        Self {
+            textures: self.textures.clone(),
+            formats: self.formats.clone(),
+            display: self.display.clone(),
+            size: self.size,
+            previous: self.previous.clone(),
+            generation: self.generation,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> ExtractResource for SimulationImages<S> {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.clone()
+    }
+}
+
+#[derive(Resource)]
+struct SimulationBindGroups<S: ComputeSimulation> {
+    /// Indexed by a mask of the state textures: bind group `front` reads
+    /// index `front >> i & 1` of texture `i` and writes the other one.
+    textures: Vec<BindGroup>,
+    bindings: BindGroup,
+    grid: BindGroup,
+    /// Per state texture, reads index `i` of the texture from before the last
+    /// resize and writes index 0 of the new one. Empty unless a resample is pending.
+    resample: Vec<[BindGroup; 2]>,
+    /// Stamps the pattern into the buffers the init entry point writes.
+    pattern: Option<BindGroup>,
+    /// [`SimulationImages::generation`] of the textures bound above.
+    generation: u32,
+    /// [`PatternImage::generation`] of the pattern bound above, 0 without one.
+    pattern_generation: u32,
+    /// Stamp the spawned patterns over index `i` of the first state texture
+    /// into index `1 - i`.
+    spawns: Vec<[BindGroup; 2]>,
+    /// [`PatternSpawns::generation`] of the spawns bound above.
+    spawn_generation: u32,
+    /// Paints the brush dabs over index `i` of the first state texture into
+    /// index `1 - i`.
+    brush: Option<[BindGroup; 2]>,
+    /// [`BrushStrokes::generation`] of the dabs bound above.
+    brush_generation: u32,
+    /// [`SimulationPipeline::formats`] of the layouts bound above.
+    formats: Vec<TextureFormat>,
+    marker: PhantomData<fn() -> S>,
+}
+
+/// Everything the bind groups of `S` are made from.
+#[derive(SystemParam)]
+struct BindGroupInputs<'w, S: ComputeSimulation> {
+    pipeline: Res<'w, SimulationPipeline<S>>,
+    gpu_images: Res<'w, RenderAssets<Image>>,
+    fallback_image: Res<'w, FallbackImage>,
+    simulation: ResMut<'w, S>,
+    images: Res<'w, SimulationImages<S>>,
+    params: Res<'w, <S as ComputeSimulation>::Params>,
+    mask: Res<'w, ObstacleMask>,
+    pattern: Option<Res<'w, PatternImage<S>>>,
+    spawns: Res<'w, PatternSpawns<S>>,
+    strokes: Res<'w, BrushStrokes<S>>,
+    /// The bind groups of the last frame, reused where their inputs are unchanged.
+    bind_groups: Option<Res<'w, SimulationBindGroups<S>>>,
+    render_device: Res<'w, RenderDevice>,
+    render_queue: Res<'w, RenderQueue>,
+}
+
+fn prepare_bind_groups<S: ComputeSimulation>(mut commands: Commands, inputs: BindGroupInputs<S>) {
+    let BindGroupInputs {
+        pipeline,
+        gpu_images,
+        fallback_image,
+        mut simulation,
+        images,
+        params,
+        mask,
+        pattern,
+        spawns,
+        strokes,
+        bind_groups,
+        render_device,
+        render_queue,
+    } = inputs;
+
+    // the texture bind groups only change with the textures or their layout
+    let texture_bind_groups = match &bind_groups {
+        Some(bind_groups) if !images.is_changed() && bind_groups.formats == pipeline.formats => {
+            bind_groups.textures.clone()
+        }
+        _ => {
+            let Some(texture_bind_groups) = (0..1 << images.textures.len()).map(|front: u32| {
+                let mut entries = Vec::new();
+                for (i, texture) in images.textures.iter().enumerate() {
+                    let read = (front >> i & 1) as usize;
+                    let input = gpu_images.get(&texture[read])?;
+                    let output = gpu_images.get(&texture[1 - read])?;
+                    entries.push(BindGroupEntry {
+                        binding: 2 * i as u32,
+                        resource: BindingResource::TextureView(&input.texture_view),
+                    });
+                    entries.push(BindGroupEntry {
+                        binding: 2 * i as u32 + 1,
+                        resource: BindingResource::TextureView(&output.texture_view),
+                    });
+                }
+                Some(render_device.create_bind_group(None, &pipeline.texture_bind_group_layout, &entries))
+            }).collect::<Option<Vec<_>>>() else {
+                return;
+            };
+            texture_bind_groups
+        }
+    };
+
+    // the parameter bindings are only rebuilt when the parameters or the grid change
+    let bindings = match &bind_groups {
+        Some(bind_groups) if !params.is_changed() && !images.is_changed() => {
+            bind_groups.bindings.clone()
+        }
+        _ => match simulation.bindings(&params, images.size).as_bind_group(
+            &pipeline.bindings_layout,
+            &render_device,
+            &gpu_images,
+            &fallback_image,
+        ) {
+            Ok(prepared) => prepared.bind_group,
+            // an image it references is not uploaded yet
+            Err(AsBindGroupError::RetryNextUpdate) => return,
+        },
+    };
+
+    let grid = match &bind_groups {
+        Some(bind_groups) if !images.is_changed() && !params.is_changed() && !mask.is_changed() => {
+            bind_groups.grid.clone()
+        }
+        _ => {
+            let (Some(display), Some(obstacles)) =
+                (gpu_images.get(&images.display), gpu_images.get(&mask.image))
+            else {
+                return;
+            };
+            let mut buffer = UniformBuffer::from(GridUniform {
+                size: images.size.as_ivec2(),
+                boundary: S::boundary(&params) as u32,
+            });
+            buffer.write_buffer(&render_device, &render_queue);
+            render_device.create_bind_group(
+                None,
+                &pipeline.grid_layout,
+                &BindGroupEntries::sequential((
+                    buffer.binding().unwrap(),
+                    &display.texture_view,
+                    &obstacles.texture_view,
+                )),
+            )
+        }
+    };
+
+    let pattern_bind_group = match (&pattern, &bind_groups) {
+        (None, _) => None,
+        (Some(pattern), Some(bind_groups)) if !pattern.is_changed() && !images.is_changed() => {
+            bind_groups.pattern.clone()
+        }
+        (Some(_), _) if pipeline.stamp.is_none() => None,
+        (Some(pattern), _) => {
+            let Some(bind_group) = pipeline.stamp.as_ref().unwrap().bind_group(
+                &gpu_images,
+                &render_device,
+                &render_queue,
+                &images.textures[0],
+                images.size,
+                &pattern.image,
+                &pattern.placement,
+                // the init entry point writes index 0
+                1,
+            ) else {
+                return;
+            };
+            Some(bind_group)
+        }
+    };
+
+    let spawn_bind_groups = match &bind_groups {
+        Some(bind_groups) if !spawns.is_changed() && !images.is_changed() => {
+            bind_groups.spawns.clone()
+        }
+        _ if pipeline.stamp.is_none() => Vec::new(),
+        _ => {
+            let Some(spawn_bind_groups) = spawns
+                .stamps
+                .iter()
+                .map(|(image, placement)| {
+                    pipeline.stamp.as_ref().unwrap().bind_groups(
+                        &gpu_images,
+                        &render_device,
+                        &render_queue,
+                        &images.textures[0],
+                        images.size,
+                        image,
+                        placement,
+                    )
+                })
+                .collect::<Option<Vec<_>>>()
+            else {
+                return;
+            };
+            spawn_bind_groups
+        }
+    };
+
+    let brush_bind_group = match &bind_groups {
+        Some(bind_groups) if !strokes.is_changed() && !images.is_changed() => bind_groups.brush.clone(),
+        _ if strokes.dabs.is_empty() || pipeline.dabs.is_none() => None,
+        _ => {
+            let Some(bind_groups) = pipeline.dabs.as_ref().unwrap().bind_groups(
+                &gpu_images,
+                &render_device,
+                &render_queue,
+                &images.textures[0],
+                &strokes.dabs,
+            ) else {
+                return;
+            };
+            Some(bind_groups)
+        }
+    };
+
+    let resample = pipeline.resample.as_ref().map_or_else(Vec::new, |resample| {
+        resample.bind_groups(&gpu_images, &render_device, &images.previous, &images.textures)
+    });
+
+    commands.insert_resource(SimulationBindGroups::<S> {
+        textures: texture_bind_groups,
+        bindings,
+        grid,
+        resample,
+        pattern: pattern_bind_group,
+        generation: images.generation,
+        pattern_generation: pattern.as_ref().map_or(0, |pattern| pattern.generation),
+        spawns: spawn_bind_groups,
+        spawn_generation: spawns.generation,
+        brush: brush_bind_group,
+        brush_generation: strokes.generation,
+        formats: pipeline.formats.clone(),
+        marker: PhantomData,
+    });
+}
+
+#[derive(Resource)]
+pub struct SimulationPipeline<S: ComputeSimulation> {
+    texture_bind_group_layout: BindGroupLayout,
+    bindings_layout: BindGroupLayout,
+    grid_layout: BindGroupLayout,
+    shader: Handle<Shader>,
+    /// Kept alive so that the shaders can import it.
+    grid_shader: Handle<Shader>,
+    shader_defs: Vec<ShaderDefVal>,
+    /// Compiled pipelines by entry point.
+    pipelines: HashMap<&'static str, CachedComputePipelineId>,
+    /// `None` when a state format is not one of [`STORAGE_FORMATS`], as
+    /// are the pipelines of the first state texture below.
+    resample: Option<ResamplePipelines>,
+    stamp: Option<StampPipelines>,
+    dabs: Option<DabPipeline>,
+    /// Formats of the state textures the layouts above are made for.
+    formats: Vec<TextureFormat>,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> SimulationPipeline<S> {
+    fn queue(&mut self, pipeline_cache: &PipelineCache, entry_point: &'static str) {
+        if self.pipelines.contains_key(entry_point) {
+            return;
+        }
+        let id = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: vec![
+                self.texture_bind_group_layout.clone(),
+                self.bindings_layout.clone(),
+                self.grid_layout.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            shader: self.shader.clone(),
+            shader_defs: self.shader_defs.clone(),
+            entry_point: Cow::from(entry_point),
+        });
+        self.pipelines.insert(entry_point, id);
+    }
+
+    /// The pipeline of `entry_point`, if it has finished compiling.
+    fn get<'a>(&self, pipeline_cache: &'a PipelineCache, entry_point: &str) -> Option<&'a ComputePipeline> {
+        pipeline_cache.get_compute_pipeline(*self.pipelines.get(entry_point)?)
+    }
+
+    /// Creates the layouts for state textures of `formats` and queues the
+    /// pipelines that do not depend on the parameters.
+    fn new(
+        render_device: &RenderDevice,
+        asset_server: &AssetServer,
+        pipeline_cache: &PipelineCache,
+        simulation: &S,
+        formats: Vec<TextureFormat>,
+    ) -> Self {
+        let mut entries = Vec::new();
+        for (i, &format) in formats.iter().enumerate() {
+            entries.push(
+                binding_types::texture_2d(TextureSampleType::Float { filterable: false })
+                    .build(2 * i as u32, ShaderStages::COMPUTE),
+            );
+            entries.push(
+                binding_types::texture_storage_2d(format, StorageTextureAccess::WriteOnly)
+                    .build(2 * i as u32 + 1, ShaderStages::COMPUTE),
+            );
+        }
+        let texture_bind_group_layout = render_device.create_bind_group_layout(None, &entries);
+        let bindings_layout = S::Bindings::bind_group_layout(render_device);
+        let grid_layout = render_device.create_bind_group_layout(
+            None,
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    binding_types::uniform_buffer::<GridUniform>(false),
+                    binding_types::texture_storage_2d(DISPLAY_FORMAT, StorageTextureAccess::WriteOnly),
+                    binding_types::texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
+            ),
+        );
+        let shader = asset_server.load(S::SHADER);
+        let grid_shader = asset_server.load(GRID_SHADER);
+        // the pattern and the brush go into the first state texture
+        let group_layouts = [bindings_layout.clone(), grid_layout.clone()];
+        if let Some(format) = formats.iter().find(|format| storage_format_def(**format).is_none()) {
+            error!(
+                "{} stores its state in {format:?}, which is not one of {:?}; it can not be resampled, \
+                 stamped or painted",
+                S::NAME,
+                STORAGE_FORMATS.map(|(format, _)| format)
+            );
+        }
+        let stamp = StampPipelines::new(render_device, asset_server, pipeline_cache, formats[0], &group_layouts);
+        let dabs = DabPipeline::new(
+            render_device,
+            asset_server,
+            pipeline_cache,
+            formats[0],
+            &group_layouts,
+            S::UNIT_STATE,
+        );
+
+        let mut pipeline = SimulationPipeline {
+            texture_bind_group_layout,
+            bindings_layout,
+            grid_layout,
+            shader,
+            grid_shader,
+            shader_defs: simulation.shader_defs(&formats),
+            pipelines: HashMap::default(),
+            resample: ResamplePipelines::new(render_device, asset_server, pipeline_cache, &formats),
+            stamp,
+            dabs,
+            formats,
+            marker: PhantomData,
+        };
+        // the update passes depend on the parameters, which are only
+        // extracted later, see `queue_pipelines`
+        pipeline.queue(pipeline_cache, S::INIT_ENTRY_POINT);
+        pipeline.queue(pipeline_cache, S::DISPLAY_ENTRY_POINT);
+        pipeline
+    }
+}
+
+// パイプラインの初期化を定義
+impl<S: ComputeSimulation> FromWorld for SimulationPipeline<S> {
+    fn from_world(world: &mut World) -> Self {
+        // the parameters are only extracted later, `queue_pipelines` rebuilds
+        // the pipeline if they ask for other formats
+        let formats = world.resource::<S>().textures(&S::Params::default());
+        Self::new(
+            world.resource::<RenderDevice>(),
+            world.resource::<AssetServer>(),
+            world.resource::<PipelineCache>(),
+            world.resource::<S>(),
+            formats,
+        )
+    }
+}
+
+/// Formats the resample, pattern and brush shaders declare their storage
+/// texture in, with the shader def picking each.
+const STORAGE_FORMATS: [(TextureFormat, &str); 6] = [
+    (TextureFormat::Rgba8Unorm, "RGBA8UNORM"),
+    (TextureFormat::Rgba16Float, "RGBA16FLOAT"),
+    (TextureFormat::Rgba32Float, "RGBA32FLOAT"),
+    (TextureFormat::R16Float, "R16FLOAT"),
+    (TextureFormat::R32Float, "R32FLOAT"),
+    (TextureFormat::Rg32Float, "RG32FLOAT"),
+];
+
+/// Shader def naming a storage texture format, used by `resample.compute.wgsl`
+/// and by simulations whose state format is configurable. `None` for formats
+/// outside of [`STORAGE_FORMATS`].
+pub fn storage_format_def(format: TextureFormat) -> Option<&'static str> {
+    STORAGE_FORMATS
+        .iter()
+        .find(|(supported, _)| *supported == format)
+        .map(|(_, def)| *def)
+}
+
+/// Queues the pipelines of the update passes the current parameters and grid
+/// ask for, and starts over with new layouts when the state formats changed.
+fn queue_pipelines<S: ComputeSimulation>(
+    mut pipeline: ResMut<SimulationPipeline<S>>,
+    pipeline_cache: Res<PipelineCache>,
+    render_device: Res<RenderDevice>,
+    asset_server: Res<AssetServer>,
+    simulation: Res<S>,
+    params: Res<S::Params>,
+    images: Res<SimulationImages<S>>,
+) {
+    if images.formats != pipeline.formats {
+        *pipeline = SimulationPipeline::new(
+            &render_device,
+            &asset_server,
+            &pipeline_cache,
+            &*simulation,
+            images.formats.clone(),
+        );
+    }
+    if params.is_changed() || images.is_changed() {
+        for pass in simulation.update_passes(&params, images.size) {
+            pipeline.queue(&pipeline_cache, pass.entry_point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_grid_sizes() {
+        assert_eq!("600x400".parse::<GridSize>().unwrap().size, UVec2::new(600, 400));
+        assert_eq!(" 64 x 32 ".parse::<GridSize>().unwrap().size, UVec2::new(64, 32));
+        for size in ["600", "0x400", "600x", "ax4", "600x400x2", "-1x4"] {
+            assert!(size.parse::<GridSize>().is_err(), "accepted `{size}`");
+        }
+    }
+
+    #[test]
+    fn passes_write_the_textures_they_declare() {
+        assert_eq!(UpdatePass::new("update").writes & 0b1111, 0b1111);
+        assert_eq!(UpdatePass::new("update").writes(&[0, 2]).writes, 0b101);
+        assert_eq!(UpdatePass::new("update").bindings_only().writes, 0);
+    }
+
+    #[test]
+    fn storage_format_defs_match_the_shaders() {
+        let shaders = [
+            include_str!("../assets/shaders/resample.compute.wgsl"),
+            include_str!("../assets/shaders/pattern.compute.wgsl"),
+            include_str!("../assets/shaders/brush.compute.wgsl"),
+        ];
+        for (format, def) in STORAGE_FORMATS {
+            assert_eq!(storage_format_def(format), Some(def));
+            // r32float is the fallback of the `#else` branch
+            let branch = match format {
+                TextureFormat::R32Float => "#else\n".to_string(),
+                _ => format!("ifdef {def}\n"),
+            };
+            for shader in shaders {
+                let declaration = shader[shader.find(&branch).expect(def)..].lines().nth(1).unwrap();
+                assert!(declaration.contains(&format!("<{}, write>", def.to_lowercase())), "{declaration}");
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use bevy::{
+    prelude::*,
+    render::extract_resource::{ExtractResource, ExtractResourcePlugin},
+};
+
+/// Fastest rate the keyboard shortcuts go up to.
+const MAX_STEPS_PER_FRAME: f32 = 64.0;
+
+/// How fast the simulations advance, shared by all of them: space pauses and
+/// resumes, the period key takes a single step while paused, plus and minus
+/// double and halve the rate.
+#[derive(Resource, Clone, Debug)]
+pub struct SimulationControl {
+    pub paused: bool,
+    /// Steps per rendered frame. Fractional rates are kept by an accumulator,
+    /// 0.25 takes a step every fourth frame.
+    pub steps_per_frame: f32,
+    /// Steps that fell short of a whole one, carried over to the next frame.
+    accumulator: f32,
+    /// Single steps requested while paused.
+    requested: u32,
+    /// Steps the render nodes take this frame.
+    steps: u32,
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            steps_per_frame: 1.0,
+            accumulator: 0.0,
+            requested: 0,
+            steps: 0,
+        }
+    }
+}
+
+impl SimulationControl {
+    /// Takes a single step on the next frame, also while paused.
+    pub fn step(&mut self) {
+        self.step_by(1);
+    }
+
+    /// Takes `steps` extra steps on the next frame, also while paused.
+    pub fn step_by(&mut self, steps: u32) {
+        self.requested += steps;
+    }
+}
+
+/// The render world side of [`SimulationControl`]: the number of steps the
+/// simulation nodes take this frame.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub(crate) struct SimulationSteps(pub u32);
+
+impl ExtractResource for SimulationSteps {
+    type Source = SimulationControl;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        Self(source.steps)
+    }
+}
+
+pub(super) struct SimulationControlPlugin;
+
+impl Plugin for SimulationControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationControl>()
+            .add_plugins(ExtractResourcePlugin::<SimulationSteps>::default())
+            .add_systems(Update, control_keys)
+            // after anything else touched the control this frame
+            .add_systems(PostUpdate, accumulate_steps);
+    }
+}
+
+fn control_keys(keys: Res<ButtonInput<KeyCode>>, mut control: ResMut<SimulationControl>) {
+    if keys.just_pressed(KeyCode::Space) {
+        control.paused = !control.paused;
+        info!("simulation {}", if control.paused { "paused" } else { "running" });
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        control.step();
+    }
+    if keys.any_just_pressed([KeyCode::Equal, KeyCode::NumpadAdd]) {
+        control.steps_per_frame = (control.steps_per_frame * 2.0).min(MAX_STEPS_PER_FRAME);
+        info!("steps per frame: {}", control.steps_per_frame);
+    }
+    if keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        control.steps_per_frame = (control.steps_per_frame / 2.0).max(1.0 / MAX_STEPS_PER_FRAME);
+        info!("steps per frame: {}", control.steps_per_frame);
+    }
+}
+
+/// Decides how many steps the render nodes take this frame.
+fn accumulate_steps(mut control: ResMut<SimulationControl>) {
+    let requested = std::mem::take(&mut control.requested);
+    if control.paused {
+        control.accumulator = 0.0;
+        control.steps = requested;
+        return;
+    }
+    control.accumulator += control.steps_per_frame.max(0.0);
+    let steps = control.accumulator.floor();
+    control.accumulator -= steps;
+    control.steps = steps as u32 + requested;
+}
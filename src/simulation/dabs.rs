@@ -0,0 +1,105 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use std::borrow::Cow;
+
+use super::storage_format_def;
+use crate::brush::BrushDab;
+
+const BRUSH_SHADER: &str = "shaders/brush.compute.wgsl";
+
+/// Paints the dabs of the [`Brush`](crate::brush::Brush) into the first state texture.
+pub(super) struct DabPipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+}
+
+impl DabPipeline {
+    /// Queues the pipeline for a first state texture of `format`. It shares
+    /// `@group(1)` and `@group(2)` with the simulation, in `group_layouts`.
+    /// With `clamp` set the painted cells are clamped into `[0, 1]`. `None`
+    /// when the shader has no declaration for `format`.
+    pub fn new(
+        render_device: &RenderDevice,
+        asset_server: &AssetServer,
+        pipeline_cache: &PipelineCache,
+        format: TextureFormat,
+        group_layouts: &[BindGroupLayout; 2],
+        clamp: bool,
+    ) -> Option<Self> {
+        let format_def = storage_format_def(format)?;
+        let layout = render_device.create_bind_group_layout(
+            None,
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    binding_types::texture_storage_2d(format, StorageTextureAccess::WriteOnly),
+                    binding_types::storage_buffer_read_only::<Vec<BrushDab>>(false),
+                    binding_types::texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
+            ),
+        );
+        let mut shader_defs = vec![format_def.into()];
+        if clamp {
+            shader_defs.push("CLAMP_STATE".into());
+        }
+        let pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: [&layout].into_iter().chain(group_layouts).cloned().collect(),
+            push_constant_ranges: Vec::new(),
+            shader: asset_server.load(BRUSH_SHADER),
+            shader_defs,
+            entry_point: Cow::from("paint"),
+        });
+        Some(Self { layout, pipeline })
+    }
+
+    pub fn ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        pipeline_cache.get_compute_pipeline(self.pipeline).is_some()
+    }
+
+    /// Binds `dabs` to be painted over index `i` of `texture` into index `1 - i`.
+    pub fn bind_groups(
+        &self,
+        gpu_images: &RenderAssets<Image>,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        texture: &[Handle<Image>; 2],
+        dabs: &[BrushDab],
+    ) -> Option<[BindGroup; 2]> {
+        let (from_0, from_1) = (gpu_images.get(&texture[0])?, gpu_images.get(&texture[1])?);
+        let mut buffer = StorageBuffer::from(dabs.to_vec());
+        buffer.write_buffer(render_device, render_queue);
+        Some([(from_0, from_1), (from_1, from_0)].map(|(input, output)| {
+            render_device.create_bind_group(
+                None,
+                &self.layout,
+                &BindGroupEntries::sequential((
+                    &output.texture_view,
+                    buffer.binding().unwrap(),
+                    &input.texture_view,
+                )),
+            )
+        }))
+    }
+
+    /// Paints the dabs over the latest state, flipping bit 0 of `front`.
+    pub fn paint<'a>(
+        &self,
+        pass: &mut ComputePass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        bind_groups: &'a [BindGroup; 2],
+        front: &mut u32,
+        workgroups: (u32, u32),
+    ) {
+        pass.set_pipeline(pipeline_cache.get_compute_pipeline(self.pipeline).unwrap());
+        pass.set_bind_group(0, &bind_groups[(*front & 1) as usize], &[]);
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        *front ^= 1;
+    }
+}
@@ -0,0 +1,372 @@
+use bevy::{
+    asset::LoadState,
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph,
+        render_resource::*,
+        renderer::RenderContext,
+    },
+};
+use std::marker::PhantomData;
+
+use super::{
+    ComputeSimulation, PendingReadbacks, SimulationActive, SimulationBindGroups, SimulationImages, SimulationPipeline,
+    SimulationSteps, SnapshotRequest, UpdatePass, Workgroups, GRID_SHADER,
+};
+
+enum SimulationState {
+    Loading,
+    Init,
+    /// Scale the state from before a resize into the new textures.
+    Resample,
+    Update,
+}
+
+pub(super) struct SimulationNode<S: ComputeSimulation> {
+    state: SimulationState,
+    /// [`SimulationActive::active`] this frame, and whether the bind groups
+    /// match the pipeline.
+    active: bool,
+    /// [`SimulationImages::generation`] the state textures were last seeded for.
+    generation: u32,
+    /// [`PatternImage::generation`](crate::pattern::PatternImage::generation) of the pattern last stamped.
+    pattern_generation: u32,
+    /// [`SnapshotRequest::requested`] last answered.
+    snapshots_taken: u32,
+    /// Whether this frame copies the state out for a snapshot.
+    snapshot: bool,
+    /// [`PatternSpawns::generation`](crate::pattern::PatternSpawns::generation) last stamped.
+    spawns_applied: u32,
+    /// Whether this frame stamps spawned patterns before updating.
+    spawn: bool,
+    /// [`BrushStrokes::generation`](crate::brush::BrushStrokes::generation) last painted.
+    strokes_applied: u32,
+    /// Whether this frame paints brush dabs before updating.
+    paint: bool,
+    /// Update steps this frame, 0 while the update pipelines are compiling.
+    steps: u32,
+    /// Update steps taken since the state was last seeded, this frame's included.
+    steps_taken: u64,
+    /// Mask of the state textures whose latest state is in index 1 when this
+    /// frame starts, picking from [`SimulationBindGroups::textures`].
+    front: u32,
+    /// [`SimulationNode::front`] once this frame ran.
+    next_front: u32,
+    /// Whether the failure to load [`GRID_SHADER`] was logged.
+    grid_failed: bool,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Default for SimulationNode<S> {
+    fn default() -> Self {
+        Self {
+            state: SimulationState::Loading,
+            active: true,
+            generation: 0,
+            pattern_generation: 0,
+            snapshots_taken: 0,
+            snapshot: false,
+            spawns_applied: 0,
+            spawn: false,
+            strokes_applied: 0,
+            paint: false,
+            steps: 0,
+            steps_taken: 0,
+            front: 0,
+            next_front: 0,
+            grid_failed: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> SimulationNode<S> {
+    /// Moves on to the next [`SimulationState`] once its pipelines are ready.
+    fn advance(&mut self, world: &World) {
+        let pipeline = world.resource::<SimulationPipeline<S>>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        // the textures were reallocated, fill them before stepping again
+        if let Some(bind_groups) = world.get_resource::<SimulationBindGroups<S>>() {
+            if bind_groups.generation != self.generation {
+                self.generation = bind_groups.generation;
+                if let SimulationState::Update | SimulationState::Resample = self.state {
+                    let resample_ready =
+                        pipeline.resample.as_ref().is_some_and(|resample| resample.ready(pipeline_cache));
+                    self.state = if !bind_groups.resample.is_empty() && resample_ready {
+                        SimulationState::Resample
+                    } else {
+                        SimulationState::Init
+                    };
+                }
+                // new formats, whose pipelines are still compiling
+                if matches!(self.state, SimulationState::Init) && !self.seed_ready(pipeline, pipeline_cache) {
+                    self.state = SimulationState::Loading;
+                }
+                return;
+            }
+            // a new pattern was set, start over from it
+            if bind_groups.pattern_generation != self.pattern_generation {
+                self.pattern_generation = bind_groups.pattern_generation;
+                if let SimulationState::Update | SimulationState::Resample = self.state {
+                    self.state = SimulationState::Init;
+                }
+                return;
+            }
+        }
+
+        // if the corresponding pipelines have loaded, transition to the next stage
+        match self.state {
+            SimulationState::Loading => {
+                if self.seed_ready(pipeline, pipeline_cache) {
+                    self.state = SimulationState::Init;
+                } else if !self.grid_failed
+                    && world.resource::<AssetServer>().load_state(&pipeline.grid_shader) == LoadState::Failed
+                {
+                    // the pipelines importing it wait for it forever, so the
+                    // simulation stays blank
+                    error!("failed to load {GRID_SHADER}, {} can not start", S::NAME);
+                    self.grid_failed = true;
+                }
+            }
+            SimulationState::Init => {
+                if Self::update_ready(world) {
+                    self.state = SimulationState::Update;
+                }
+            }
+            SimulationState::Resample => self.state = SimulationState::Update,
+            SimulationState::Update => {}
+        }
+    }
+
+    /// Whether the pipelines of all update passes have compiled; a pass that
+    /// was just switched on may still be compiling.
+    fn update_ready(world: &World) -> bool {
+        let pipeline = world.resource::<SimulationPipeline<S>>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let images = world.resource::<SimulationImages<S>>();
+        world
+            .resource::<S>()
+            .update_passes(world.resource::<S::Params>(), images.size)
+            .iter()
+            .all(|pass| pipeline.get(pipeline_cache, pass.entry_point).is_some())
+    }
+
+    /// Whether the pipelines seeding and displaying the state have compiled.
+    fn seed_ready(&self, pipeline: &SimulationPipeline<S>, pipeline_cache: &PipelineCache) -> bool {
+        pipeline.get(pipeline_cache, S::INIT_ENTRY_POINT).is_some()
+            && pipeline.get(pipeline_cache, S::DISPLAY_ENTRY_POINT).is_some()
+            && pipeline.stamp.as_ref().is_none_or(|stamp| stamp.stamp_ready(pipeline_cache))
+    }
+
+    /// Mask of the state textures a step leaves in the other index.
+    fn step_swaps(passes: &[UpdatePass]) -> u32 {
+        passes
+            .iter()
+            .filter(|pass| pass.repeat % 2 == 1)
+            .fold(0, |swapped, pass| swapped ^ pass.writes)
+    }
+
+    /// Whether the bind groups were made for the layouts of the current
+    /// pipeline; they lag behind it for a frame or so after a format change.
+    fn bind_groups_current(world: &World) -> bool {
+        world
+            .get_resource::<SimulationBindGroups<S>>()
+            .is_some_and(|bind_groups| bind_groups.formats == world.resource::<SimulationPipeline<S>>().formats)
+    }
+}
+
+impl<S: ComputeSimulation> render_graph::Node for SimulationNode<S> {
+    fn update(&mut self, world: &mut World) {
+        self.front = self.next_front;
+        // an inactive simulation stays where it was, seeding included
+        self.active = world
+            .get_resource::<SimulationActive<S>>()
+            .is_none_or(|active| active.active)
+            && Self::bind_groups_current(world);
+        if !self.active {
+            self.snapshot = false;
+            self.spawn = false;
+            self.paint = false;
+            self.steps = 0;
+            return;
+        }
+        self.advance(world);
+
+        self.steps = match self.state {
+            SimulationState::Update if Self::update_ready(world) => {
+                world.get_resource::<SimulationSteps>().map_or(1, |steps| steps.0)
+            }
+            _ => 0,
+        };
+        match self.state {
+            SimulationState::Update => self.steps_taken += self.steps as u64,
+            SimulationState::Resample => {}
+            _ => self.steps_taken = 0,
+        }
+
+        // snapshots are only taken of a running simulation
+        let requested = world
+            .get_resource::<SnapshotRequest<S>>()
+            .map_or(0, |request| request.requested);
+        self.snapshot = matches!(self.state, SimulationState::Update) && requested != self.snapshots_taken;
+        if self.snapshot {
+            self.snapshots_taken = requested;
+        }
+
+        // patterns spawned while seeding are lost in the new state anyway
+        let spawned = world
+            .get_resource::<SimulationBindGroups<S>>()
+            .map_or(self.spawns_applied, |bind_groups| bind_groups.spawn_generation);
+        let pipeline = world.resource::<SimulationPipeline<S>>();
+        let spawn_ready = pipeline
+            .stamp
+            .as_ref()
+            .is_some_and(|stamp| stamp.stamp_over_ready(world.resource::<PipelineCache>()));
+        self.spawn = matches!(self.state, SimulationState::Update) && spawned != self.spawns_applied && spawn_ready;
+        if self.spawn || !matches!(self.state, SimulationState::Update) {
+            self.spawns_applied = spawned;
+        }
+
+        let (painted, has_dabs) = world
+            .get_resource::<SimulationBindGroups<S>>()
+            .map_or((self.strokes_applied, false), |bind_groups| {
+                (bind_groups.brush_generation, bind_groups.brush.is_some())
+            });
+        let pipeline = world.resource::<SimulationPipeline<S>>();
+        let paint_ready = pipeline
+            .dabs
+            .as_ref()
+            .is_some_and(|dabs| dabs.ready(world.resource::<PipelineCache>()));
+        self.paint = matches!(self.state, SimulationState::Update)
+            && painted != self.strokes_applied
+            && has_dabs
+            && paint_ready;
+        if self.paint || !matches!(self.state, SimulationState::Update) {
+            self.strokes_applied = painted;
+        }
+
+        self.next_front = match self.state {
+            SimulationState::Loading => self.front,
+            // both write index 0 of every texture
+            SimulationState::Init | SimulationState::Resample => 0,
+            SimulationState::Update => {
+                // spawned patterns and brush dabs only write the first texture
+                let spawns = world.resource::<SimulationBindGroups<S>>().spawns.len() as u32;
+                let stamps = if self.spawn { spawns } else { 0 } + self.paint as u32;
+                let passes = world
+                    .resource::<S>()
+                    .update_passes(world.resource::<S::Params>(), world.resource::<SimulationImages<S>>().size);
+                let textures = (1 << world.resource::<SimulationImages<S>>().textures.len()) - 1;
+                let swapped = if self.steps % 2 == 1 { Self::step_swaps(&passes) & textures } else { 0 };
+                self.front ^ (stamps & 1) ^ swapped
+            }
+        };
+    }
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(bind_groups) = world.get_resource::<SimulationBindGroups<S>>() else {
+            return Ok(());
+        };
+        if !self.active {
+            return Ok(());
+        }
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<SimulationPipeline<S>>();
+        let simulation = world.resource::<S>();
+        let params = world.resource::<S::Params>();
+        let images = world.resource::<SimulationImages<S>>();
+        let workgroups = Workgroups::Tiles.count(images.size);
+
+        // see `SimulationNode::front`
+        let mut front = self.front;
+        {
+            let mut pass = render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor::default());
+
+            // the stamp and brush pipelines read the walls from these too
+            pass.set_bind_group(1, &bind_groups.bindings, &[]);
+            pass.set_bind_group(2, &bind_groups.grid, &[]);
+
+            // stamp spawned patterns over the latest state, then paint the brush dabs over them
+            if let (true, Some(stamp)) = (self.spawn, &pipeline.stamp) {
+                stamp.stamp_over(&mut pass, pipeline_cache, &bind_groups.spawns, &mut front, workgroups);
+            }
+            if let (true, Some(dabs), Some(brush)) = (self.paint, &pipeline.dabs, &bind_groups.brush) {
+                dabs.paint(&mut pass, pipeline_cache, brush, &mut front, workgroups);
+            }
+
+            // select the pipelines based on the current state
+            match self.state {
+                SimulationState::Loading => {}
+                SimulationState::Init => {
+                    let init_pipeline = pipeline
+                        .get(pipeline_cache, S::INIT_ENTRY_POINT)
+                        .unwrap();
+                    // write the initial state into index 0 of every texture
+                    front = 0;
+                    pass.set_bind_group(0, bind_groups.textures.last().unwrap(), &[]);
+                    pass.set_pipeline(init_pipeline);
+                    pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+                    if let (Some(stamp), Some(pattern)) = (&pipeline.stamp, &bind_groups.pattern) {
+                        stamp.stamp(&mut pass, pipeline_cache, pattern, workgroups);
+                    }
+                }
+                SimulationState::Resample => {
+                    // `front` still describes the textures from before the resize
+                    if let Some(resample) = &pipeline.resample {
+                        resample.resample(&mut pass, pipeline_cache, &bind_groups.resample, front, workgroups);
+                    }
+                    front = 0;
+                }
+                SimulationState::Update => {
+                    let passes = simulation.update_passes(params, images.size);
+                    let pipelines = passes
+                        .iter()
+                        .map(|update_pass| pipeline.get(pipeline_cache, update_pass.entry_point))
+                        .collect::<Option<Vec<_>>>()
+                        .unwrap_or_default();
+                    let textures = (1 << images.textures.len()) - 1;
+                    for _ in 0..self.steps {
+                        for (update_pass, update_pipeline) in passes.iter().zip(&pipelines) {
+                            pass.set_pipeline(update_pipeline);
+                            let (x, y) = update_pass.workgroups.count(images.size);
+                            for _ in 0..update_pass.repeat {
+                                pass.set_bind_group(0, &bind_groups.textures[front as usize], &[]);
+                                pass.dispatch_workgroups(x, y, 1);
+                                front ^= update_pass.writes & textures;
+                            }
+                        }
+                    }
+                }
+            }
+
+            // draw the latest state into the sprite's colour image
+            if !matches!(self.state, SimulationState::Loading) {
+                let display_pipeline = pipeline
+                    .get(pipeline_cache, S::DISPLAY_ENTRY_POINT)
+                    .unwrap();
+                pass.set_pipeline(display_pipeline);
+                pass.set_bind_group(0, &bind_groups.textures[front as usize], &[]);
+                pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+            }
+        }
+
+        if self.snapshot {
+            let gpu_images = world.resource::<RenderAssets<Image>>();
+            let state = gpu_images.get(&images.textures[0][(front & 1) as usize]).unwrap();
+            world
+                .resource::<PendingReadbacks<S>>()
+                .copy(render_context, state, self.steps_taken);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, ImageCopyBuffer, ImageDataLayout, MapMode, Maintain,
+            TextureFormat,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::{GpuImage, TextureFormatPixelInfo},
+    },
+};
+use std::{
+    marker::PhantomData,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+};
+
+use super::ComputeSimulation;
+
+/// The first state texture of a simulation, copied back from the GPU.
+#[derive(Clone, Debug)]
+pub struct StateSnapshot {
+    pub size: UVec2,
+    pub format: TextureFormat,
+    /// Texels row by row from the top left, without padding.
+    pub data: Vec<u8>,
+    /// Update steps taken since the state was last seeded.
+    pub step: u64,
+}
+
+impl StateSnapshot {
+    /// Value of the cell at `(x, y)`, missing components read as zero.
+    pub fn cell(&self, x: u32, y: u32) -> Vec4 {
+        let texel_size = self.format.pixel_size();
+        let offset = (y * self.size.x + x) as usize * texel_size;
+        let texel = &self.data[offset..offset + texel_size];
+        let mut value = Vec4::ZERO;
+        match self.format {
+            TextureFormat::R32Float | TextureFormat::Rg32Float | TextureFormat::Rgba32Float => {
+                for (i, bytes) in texel.chunks_exact(4).enumerate() {
+                    value[i] = f32::from_le_bytes(bytes.try_into().unwrap());
+                }
+            }
+            TextureFormat::R16Float | TextureFormat::Rgba16Float => {
+                for (i, bytes) in texel.chunks_exact(2).enumerate() {
+                    value[i] = f16_to_f32(u16::from_le_bytes(bytes.try_into().unwrap()));
+                }
+            }
+            TextureFormat::Rgba8Unorm => {
+                for (i, byte) in texel.iter().enumerate() {
+                    value[i] = *byte as f32 / 255.0;
+                }
+            }
+            format => warn_once!("can not decode {format:?} snapshots"),
+        }
+        value
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Asks for [`StateSnapshot`]s of `S`. Each [`StateSnapshots::request`] is
+/// answered by a [`SnapshotTaken`] event a frame or two later, taken after
+/// the update passes of the frame it is picked up in.
+#[derive(Resource)]
+pub struct StateSnapshots<S: ComputeSimulation> {
+    requested: u32,
+    sender: Sender<StateSnapshot>,
+    receiver: Mutex<Receiver<StateSnapshot>>,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Default for StateSnapshots<S> {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            requested: 0,
+            sender,
+            receiver: Mutex::new(receiver),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> StateSnapshots<S> {
+    pub fn request(&mut self) {
+        self.requested = self.requested.wrapping_add(1);
+    }
+}
+
+#[derive(Event)]
+pub struct SnapshotTaken<S: ComputeSimulation> {
+    pub snapshot: StateSnapshot,
+    marker: PhantomData<fn() -> S>,
+}
+
+/// The render world side of [`StateSnapshots`].
+#[derive(Resource)]
+pub(super) struct SnapshotRequest<S: ComputeSimulation> {
+    /// Number of snapshots requested so far.
+    pub requested: u32,
+    sender: Sender<StateSnapshot>,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> ExtractResource for SnapshotRequest<S> {
+    type Source = StateSnapshots<S>;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        Self {
+            requested: source.requested,
+            sender: source.sender.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// A texture copy waiting to be mapped once the frame is submitted.
+struct PendingReadback {
+    buffer: Buffer,
+    size: UVec2,
+    format: TextureFormat,
+    padded_bytes_per_row: usize,
+    step: u64,
+}
+
+/// Copies the render graph node queued this frame.
+#[derive(Resource)]
+pub(super) struct PendingReadbacks<S: ComputeSimulation> {
+    readbacks: Mutex<Vec<PendingReadback>>,
+    marker: PhantomData<fn() -> S>,
+}
+
+impl<S: ComputeSimulation> Default for PendingReadbacks<S> {
+    fn default() -> Self {
+        Self {
+            readbacks: Mutex::new(Vec::new()),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S: ComputeSimulation> PendingReadbacks<S> {
+    /// Copies `state` into a buffer that is read back once the frame is
+    /// submitted, `step` update steps after the state was seeded.
+    pub fn copy(&self, render_context: &mut RenderContext, state: &GpuImage, step: u64) {
+        let size = UVec2::new(state.texture.width(), state.texture.height());
+        let padded_bytes_per_row =
+            RenderDevice::align_copy_bytes_per_row(size.x as usize * state.texture_format.pixel_size());
+        let buffer = render_context.render_device().create_buffer(&BufferDescriptor {
+            label: None,
+            size: (padded_bytes_per_row * size.y as usize) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        render_context.command_encoder().copy_texture_to_buffer(
+            state.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row as u32),
+                    rows_per_image: None,
+                },
+            },
+            state.texture.size(),
+        );
+        self.readbacks.lock().unwrap().push(PendingReadback {
+            buffer,
+            size,
+            format: state.texture_format,
+            padded_bytes_per_row,
+            step,
+        });
+    }
+}
+
+/// Waits for the copies of this frame and hands them to the main world.
+pub(super) fn map_readbacks<S: ComputeSimulation>(
+    pending: Res<PendingReadbacks<S>>,
+    request: Option<Res<SnapshotRequest<S>>>,
+    render_device: Res<RenderDevice>,
+) {
+    let readbacks = std::mem::take(&mut *pending.readbacks.lock().unwrap());
+    let Some(request) = request else {
+        return;
+    };
+    for readback in readbacks {
+        let slice = readback.buffer.slice(..);
+        slice.map_async(MapMode::Read, |_| {});
+        render_device.poll(Maintain::Wait);
+        let row_bytes = readback.size.x as usize * readback.format.pixel_size();
+        let data = slice
+            .get_mapped_range()
+            .chunks(readback.padded_bytes_per_row)
+            .flat_map(|row| &row[..row_bytes])
+            .copied()
+            .collect();
+        readback.buffer.unmap();
+        // the main world may be gone when shutting down
+        let _ = request.sender.send(StateSnapshot {
+            size: readback.size,
+            format: readback.format,
+            data,
+            step: readback.step,
+        });
+    }
+}
+
+pub(super) fn receive_snapshots<S: ComputeSimulation>(
+    snapshots: Res<StateSnapshots<S>>,
+    mut events: EventWriter<SnapshotTaken<S>>,
+) {
+    let receiver = snapshots.receiver.lock().unwrap();
+    events.send_batch(receiver.try_iter().map(|snapshot| SnapshotTaken {
+        snapshot,
+        marker: PhantomData,
+    }));
+}
@@ -0,0 +1,115 @@
+use bevy::{
+    prelude::*,
+    render::{render_asset::RenderAssets, render_resource::*, renderer::RenderDevice},
+};
+use std::borrow::Cow;
+
+use super::storage_format_def;
+
+const RESAMPLE_SHADER: &str = "shaders/resample.compute.wgsl";
+
+/// Per state texture, scales a texture of the old size into the new one.
+pub(super) struct ResamplePipelines {
+    layouts: Vec<BindGroupLayout>,
+    pipelines: Vec<CachedComputePipelineId>,
+}
+
+impl ResamplePipelines {
+    /// Queues a pipeline for each of the state texture `formats`. `None` when
+    /// the shader has no declaration for one of them.
+    pub fn new(
+        render_device: &RenderDevice,
+        asset_server: &AssetServer,
+        pipeline_cache: &PipelineCache,
+        formats: &[TextureFormat],
+    ) -> Option<Self> {
+        let format_defs = formats
+            .iter()
+            .map(|&format| storage_format_def(format))
+            .collect::<Option<Vec<_>>>()?;
+        let layouts: Vec<_> = formats
+            .iter()
+            .map(|&format| {
+                render_device.create_bind_group_layout(
+                    None,
+                    &BindGroupLayoutEntries::sequential(
+                        ShaderStages::COMPUTE,
+                        (
+                            binding_types::texture_2d(TextureSampleType::Float { filterable: false }),
+                            binding_types::texture_storage_2d(format, StorageTextureAccess::WriteOnly),
+                        ),
+                    ),
+                )
+            })
+            .collect();
+        let shader = asset_server.load(RESAMPLE_SHADER);
+        let pipelines = format_defs
+            .iter()
+            .zip(&layouts)
+            .map(|(&format_def, layout)| {
+                pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: None,
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: Vec::new(),
+                    shader: shader.clone(),
+                    shader_defs: vec![format_def.into()],
+                    entry_point: Cow::from("resample"),
+                })
+            })
+            .collect();
+        Some(Self { layouts, pipelines })
+    }
+
+    pub fn ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        self.pipelines
+            .iter()
+            .all(|id| pipeline_cache.get_compute_pipeline(*id).is_some())
+    }
+
+    /// Per state texture, reads index `i` of the texture from before the
+    /// resize and writes index 0 of the new one. Empty while a texture is
+    /// not uploaded yet.
+    pub fn bind_groups(
+        &self,
+        gpu_images: &RenderAssets<Image>,
+        render_device: &RenderDevice,
+        previous: &[[Handle<Image>; 2]],
+        textures: &[[Handle<Image>; 2]],
+    ) -> Vec<[BindGroup; 2]> {
+        previous
+            .iter()
+            .zip(textures)
+            .zip(&self.layouts)
+            .map(|((previous, texture), layout)| {
+                let output = gpu_images.get(&texture[0])?;
+                let [from_0, from_1] = previous.each_ref().map(|previous| {
+                    let input = gpu_images.get(previous)?;
+                    Some(render_device.create_bind_group(
+                        None,
+                        layout,
+                        &BindGroupEntries::sequential((&input.texture_view, &output.texture_view)),
+                    ))
+                });
+                Some([from_0?, from_1?])
+            })
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default()
+    }
+
+    /// Scales the textures from before the resize, whose latest state is
+    /// picked by `front`, into index 0 of the new ones.
+    pub fn resample<'a>(
+        &self,
+        pass: &mut ComputePass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        bind_groups: &'a [[BindGroup; 2]],
+        front: u32,
+        workgroups: (u32, u32),
+    ) {
+        for (i, (bind_group, id)) in bind_groups.iter().zip(&self.pipelines).enumerate() {
+            pass.set_pipeline(pipeline_cache.get_compute_pipeline(*id).unwrap());
+            pass.set_bind_group(0, &bind_group[(front >> i & 1) as usize], &[]);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use std::borrow::Cow;
+
+use super::storage_format_def;
+use crate::pattern::PatternPlacement;
+use uniforms::PatternUniform;
+
+const PATTERN_SHADER: &str = "shaders/pattern.compute.wgsl";
+
+mod uniforms {
+    // see `uniforms` in simulation.rs
+    #![allow(dead_code)]
+
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    /// Where `pattern.compute.wgsl` stamps the pattern.
+    #[derive(Clone, Copy, Debug, ShaderType)]
+    pub struct PatternUniform {
+        /// Cell the centre of the pattern lands on.
+        pub center: Vec2,
+        pub scale: f32,
+        pub rotation: f32,
+    }
+}
+
+/// Stamps a pattern into the first state texture, when seeding the
+/// simulation or over the running state.
+pub(super) struct StampPipelines {
+    layout: BindGroupLayout,
+    /// Replaces the whole texture, right after the init entry point ran.
+    stamp: CachedComputePipelineId,
+    /// Replaces the cells under the pattern, for [`SpawnPattern`](crate::pattern::SpawnPattern)s.
+    stamp_over: CachedComputePipelineId,
+}
+
+impl StampPipelines {
+    /// Queues the pipelines for a first state texture of `format`. They share
+    /// `@group(1)` and `@group(2)` with the simulation, in `group_layouts`.
+    /// `None` when the shader has no declaration for `format`.
+    pub fn new(
+        render_device: &RenderDevice,
+        asset_server: &AssetServer,
+        pipeline_cache: &PipelineCache,
+        format: TextureFormat,
+        group_layouts: &[BindGroupLayout; 2],
+    ) -> Option<Self> {
+        let format_def = storage_format_def(format)?;
+        let layout = render_device.create_bind_group_layout(
+            None,
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    binding_types::texture_2d(TextureSampleType::Float { filterable: false }),
+                    binding_types::texture_storage_2d(format, StorageTextureAccess::WriteOnly),
+                    binding_types::uniform_buffer::<PatternUniform>(false),
+                    binding_types::texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
+            ),
+        );
+        let shader = asset_server.load(PATTERN_SHADER);
+        let [stamp, stamp_over] = ["stamp", "stamp_over"].map(|entry_point| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: None,
+                layout: [&layout].into_iter().chain(group_layouts).cloned().collect(),
+                push_constant_ranges: Vec::new(),
+                shader: shader.clone(),
+                shader_defs: vec![format_def.into()],
+                entry_point: Cow::from(entry_point),
+            })
+        });
+        Some(Self {
+            layout,
+            stamp,
+            stamp_over,
+        })
+    }
+
+    pub fn stamp_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        pipeline_cache.get_compute_pipeline(self.stamp).is_some()
+    }
+
+    pub fn stamp_over_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        pipeline_cache.get_compute_pipeline(self.stamp_over).is_some()
+    }
+
+    /// Binds `pattern` to be stamped at `placement` over index `read` of
+    /// `texture`, a grid of `size` cells, written to index `1 - read`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn bind_group(
+        &self,
+        gpu_images: &RenderAssets<Image>,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        texture: &[Handle<Image>; 2],
+        size: UVec2,
+        pattern: &Handle<Image>,
+        placement: &PatternPlacement,
+        read: usize,
+    ) -> Option<BindGroup> {
+        let image = gpu_images.get(pattern)?;
+        let (input, output) = (gpu_images.get(&texture[read])?, gpu_images.get(&texture[1 - read])?);
+        let mut buffer = UniformBuffer::from(PatternUniform {
+            center: size.as_vec2() / 2.0 + placement.position,
+            scale: placement.scale,
+            rotation: placement.rotation,
+        });
+        buffer.write_buffer(render_device, render_queue);
+        Some(render_device.create_bind_group(
+            None,
+            &self.layout,
+            &BindGroupEntries::sequential((
+                &image.texture_view,
+                &output.texture_view,
+                buffer.binding().unwrap(),
+                &input.texture_view,
+            )),
+        ))
+    }
+
+    /// Stamps over both indices of `texture`, see [`StampPipelines::bind_group`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn bind_groups(
+        &self,
+        gpu_images: &RenderAssets<Image>,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        texture: &[Handle<Image>; 2],
+        size: UVec2,
+        pattern: &Handle<Image>,
+        placement: &PatternPlacement,
+    ) -> Option<[BindGroup; 2]> {
+        let [from_0, from_1] = [0, 1].map(|read| {
+            self.bind_group(gpu_images, render_device, render_queue, texture, size, pattern, placement, read)
+        });
+        Some([from_0?, from_1?])
+    }
+
+    /// Stamps the pattern of `bind_group` over whatever the init entry point wrote.
+    pub fn stamp<'a>(
+        &self,
+        pass: &mut ComputePass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        bind_group: &'a BindGroup,
+        workgroups: (u32, u32),
+    ) {
+        pass.set_pipeline(pipeline_cache.get_compute_pipeline(self.stamp).unwrap());
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+    }
+
+    /// Stamps the patterns of `bind_groups` over the latest state one after
+    /// the other, so that overlapping patterns stack, flipping bit 0 of
+    /// `front` for every one.
+    pub fn stamp_over<'a>(
+        &self,
+        pass: &mut ComputePass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        bind_groups: &'a [[BindGroup; 2]],
+        front: &mut u32,
+        workgroups: (u32, u32),
+    ) {
+        pass.set_pipeline(pipeline_cache.get_compute_pipeline(self.stamp_over).unwrap());
+        for bind_group in bind_groups {
+            pass.set_bind_group(0, &bind_group[(*front & 1) as usize], &[]);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+            *front ^= 1;
+        }
+    }
+}
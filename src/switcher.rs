@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+
+use crate::flow_lenia::FlowLenia;
+use crate::fluid::Fluid;
+use crate::lenia::Lenia;
+use crate::life::Life;
+use crate::simulation::{ComputeSimulation, SimulationActive};
+
+/// The simulation on screen. Only its render graph node dispatches and only
+/// its sprite is visible; the others keep their state until switched back to.
+#[derive(States, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SimulationKind {
+    #[default]
+    Lenia,
+    FlowLenia,
+    Fluid,
+    Life,
+}
+
+impl SimulationKind {
+    const ALL: [SimulationKind; 4] = [
+        SimulationKind::Lenia,
+        SimulationKind::FlowLenia,
+        SimulationKind::Fluid,
+        SimulationKind::Life,
+    ];
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|kind| *kind == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Switches between the simulations at runtime: tab cycles through them, the
+/// number keys pick one directly. Needs the plugins of all simulations.
+pub struct SwitcherPlugin;
+
+impl Plugin for SwitcherPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<SimulationKind>()
+            .add_systems(Update, switch_simulation)
+            .add_systems(
+                PostUpdate,
+                (
+                    follow_state::<Lenia>(SimulationKind::Lenia),
+                    follow_state::<FlowLenia>(SimulationKind::FlowLenia),
+                    follow_state::<Fluid>(SimulationKind::Fluid),
+                    follow_state::<Life>(SimulationKind::Life),
+                )
+                    .run_if(state_changed::<SimulationKind>),
+            );
+    }
+}
+
+/// Activates `S` exactly while `kind` is the current [`SimulationKind`].
+fn follow_state<S: ComputeSimulation>(
+    kind: SimulationKind,
+) -> impl FnMut(Res<State<SimulationKind>>, ResMut<SimulationActive<S>>) {
+    move |state, mut active| {
+        let is_current = *state.get() == kind;
+        if active.active != is_current {
+            active.active = is_current;
+        }
+    }
+}
+
+fn switch_simulation(
+    keys: Res<ButtonInput<KeyCode>>,
+    state: Res<State<SimulationKind>>,
+    mut next_state: ResMut<NextState<SimulationKind>>,
+) {
+    let digits = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4];
+    let next = if keys.just_pressed(KeyCode::Tab) {
+        Some(state.get().next())
+    } else {
+        digits
+            .iter()
+            .zip(SimulationKind::ALL)
+            .find(|(key, _)| keys.just_pressed(**key))
+            .map(|(_, kind)| kind)
+    };
+    if let Some(next) = next.filter(|next| next != state.get()) {
+        info!("switching to {next:?}");
+        next_state.set(next);
+    }
+}
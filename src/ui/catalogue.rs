@@ -0,0 +1,301 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    window::PrimaryWindow,
+};
+
+use crate::lenia::{AnimalLibrary, Lenia, LeniaParams};
+use crate::pattern::{PatternAsset, PatternPlacement, SpawnPattern};
+use crate::simulation::{GridSize, SimulationActive};
+
+/// Side of the creature thumbnails in pixels.
+const THUMBNAIL_SIZE: f32 = 32.0;
+const SELECTED_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.25);
+const HOVERED_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.1);
+
+/// Panel listing the Lenia creatures of a library by family. Picking one
+/// switches to its rule, clicking on the grid then places it under the
+/// cursor. C shows and hides the panel.
+pub struct CataloguePlugin {
+    /// Asset path of an [`AnimalLibrary`].
+    pub path: String,
+}
+
+impl Default for CataloguePlugin {
+    fn default() -> Self {
+        Self {
+            path: "patterns/creatures.json".into(),
+        }
+    }
+}
+
+impl Plugin for CataloguePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                build_catalogue,
+                select_creature,
+                highlight_creatures,
+                spawn_creature,
+                catalogue_showhide,
+            ),
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        // the library loader comes with the Lenia plugin, which may be added later
+        let library = app.world.resource::<AssetServer>().load(self.path.clone());
+        app.insert_resource(Catalogue {
+            library,
+            patterns: Vec::new(),
+            selected: None,
+        });
+    }
+}
+
+#[derive(Resource)]
+struct Catalogue {
+    library: Handle<AnimalLibrary>,
+    /// Cells of each animal of the library, once the panel is built.
+    patterns: Vec<Handle<PatternAsset>>,
+    /// Index of the picked animal.
+    selected: Option<usize>,
+}
+
+/// Marker to find the panel so we can show/hide it
+#[derive(Component)]
+struct CatalogueRoot;
+
+/// Row of the animal at this index of the library
+#[derive(Component)]
+struct CatalogueEntry(usize);
+
+fn build_catalogue(
+    mut commands: Commands,
+    mut catalogue: ResMut<Catalogue>,
+    libraries: Res<Assets<AnimalLibrary>>,
+    mut patterns: ResMut<Assets<PatternAsset>>,
+    mut images: ResMut<Assets<Image>>,
+    roots: Query<Entity, With<CatalogueRoot>>,
+) {
+    if !roots.is_empty() {
+        return;
+    }
+    let Some(library) = libraries.get(&catalogue.library) else {
+        return;
+    };
+    let root = commands
+        .spawn((
+            CatalogueRoot,
+            Interaction::default(),
+            NodeBundle {
+                background_color: BackgroundColor(Color::BLACK.with_a(0.5)),
+                z_index: ZIndex::Global(i32::MAX),
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    // top-left corner, opposite of the FPS counter
+                    left: Val::Percent(1.),
+                    top: Val::Percent(1.),
+                    max_height: Val::Percent(98.),
+                    flex_direction: FlexDirection::Column,
+                    overflow: Overflow::clip_y(),
+                    padding: UiRect::all(Val::Px(4.0)),
+                    row_gap: Val::Px(2.0),
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .id();
+
+    let mut family = None;
+    let mut children = Vec::new();
+    catalogue.patterns.clear();
+    for (index, animal) in library.animals.iter().enumerate() {
+        let pattern = animal.pattern().unwrap_or_else(|err| {
+            warn!("can not read the cells of {}: {err}", animal.name);
+            PatternAsset::default()
+        });
+        let thumbnail = images.add(thumbnail(&pattern));
+        catalogue.patterns.push(patterns.add(pattern));
+
+        if family != Some(&animal.family) && !animal.family.is_empty() {
+            children.push(
+                commands
+                    .spawn(TextBundle::from_section(
+                        animal.family.clone(),
+                        TextStyle {
+                            font_size: 14.0,
+                            color: Color::GRAY,
+                            ..default()
+                        },
+                    ))
+                    .id(),
+            );
+        }
+        family = Some(&animal.family);
+
+        let entry = commands
+            .spawn((
+                CatalogueEntry(index),
+                ButtonBundle {
+                    background_color: BackgroundColor(Color::NONE),
+                    style: Style {
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(6.0),
+                        padding: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+            ))
+            .with_children(|entry| {
+                entry.spawn(ImageBundle {
+                    image: UiImage::new(thumbnail),
+                    style: Style {
+                        width: Val::Px(THUMBNAIL_SIZE),
+                        height: Val::Px(THUMBNAIL_SIZE),
+                        ..default()
+                    },
+                    ..default()
+                });
+                entry.spawn(TextBundle::from_section(
+                    animal.name.clone(),
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ));
+            })
+            .id();
+        children.push(entry);
+    }
+    commands.entity(root).push_children(&children);
+}
+
+/// The first channel of `pattern` as white on transparent.
+fn thumbnail(pattern: &PatternAsset) -> Image {
+    let data = match pattern.channels.first() {
+        Some(values) if !values.is_empty() => values
+            .iter()
+            .flat_map(|value| [u8::MAX, u8::MAX, u8::MAX, (value.clamp(0.0, 1.0) * 255.0) as u8])
+            .collect(),
+        _ => vec![0; 4],
+    };
+    Image::new(
+        Extent3d {
+            width: pattern.size.x.max(1),
+            height: pattern.size.y.max(1),
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    )
+}
+
+fn select_creature(
+    mut catalogue: ResMut<Catalogue>,
+    libraries: Res<Assets<AnimalLibrary>>,
+    mut params: ResMut<LeniaParams>,
+    entries: Query<(&Interaction, &CatalogueEntry), Changed<Interaction>>,
+) {
+    for (interaction, entry) in &entries {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(animal) = libraries.get(&catalogue.library).and_then(|library| library.animals.get(entry.0)) else {
+            continue;
+        };
+        match animal.params() {
+            Ok(animal_params) => {
+                *params = animal_params;
+                catalogue.selected = Some(entry.0);
+            }
+            Err(err) => warn!("can not pick {}: {err}", animal.name),
+        }
+    }
+}
+
+fn highlight_creatures(
+    catalogue: Res<Catalogue>,
+    mut entries: Query<(&Interaction, &CatalogueEntry, &mut BackgroundColor)>,
+) {
+    for (interaction, entry, mut color) in &mut entries {
+        let target = if catalogue.selected == Some(entry.0) {
+            SELECTED_COLOR
+        } else if *interaction == Interaction::Hovered {
+            HOVERED_COLOR
+        } else {
+            Color::NONE
+        };
+        if color.0 != target {
+            color.0 = target;
+        }
+    }
+}
+
+/// A left click on the grid places the picked creature under the cursor.
+/// Clicks with shift or ctrl held are left to the wall and cell brushes.
+#[allow(clippy::too_many_arguments)]
+fn spawn_creature(
+    catalogue: Res<Catalogue>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    interactions: Query<&Interaction>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    grid: Res<GridSize>,
+    active: Res<SimulationActive<Lenia>>,
+    mut spawns: EventWriter<SpawnPattern<Lenia>>,
+) {
+    if !active.active {
+        return;
+    }
+    let modifiers = [KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight];
+    if !buttons.just_pressed(MouseButton::Left) || keys.any_pressed(modifiers) {
+        return;
+    }
+    // clicks on the panel or any other UI are not meant for the grid
+    if interactions.iter().any(|interaction| *interaction != Interaction::None) {
+        return;
+    }
+    let Some(pattern) = catalogue.selected.and_then(|index| catalogue.patterns.get(index)) else {
+        return;
+    };
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    let Some(cell) = grid.cursor_cell(window, camera, camera_transform) else {
+        return;
+    };
+    spawns.send(SpawnPattern::new(
+        pattern.clone(),
+        PatternPlacement {
+            position: cell - grid.size.as_vec2() / 2.0,
+            ..default()
+        },
+    ));
+}
+
+/// Toggle the catalogue when pressing C
+fn catalogue_showhide(
+    mut q: Query<&mut Visibility, With<CatalogueRoot>>,
+    kbd: Res<ButtonInput<KeyCode>>,
+) {
+    if kbd.just_pressed(KeyCode::KeyC) {
+        for mut vis in &mut q {
+            *vis = match *vis {
+                Visibility::Hidden => Visibility::Visible,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+}